@@ -0,0 +1,316 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use serde_json_path::JsonPath;
+use toml::{Table, Value};
+
+use crate::{request::HitmanResponse, scope::Scope, util::truncate};
+
+/// The outcome of a single check in the `_assert` section.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub description: String,
+    pub failure: Option<String>,
+}
+
+impl CheckResult {
+    pub fn new(description: String, failure: Option<String>) -> Self {
+        Self {
+            description,
+            failure,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Check the response against the assertions in the `_assert` section.
+///
+/// Fails if the section itself is invalid.
+pub fn check_response(
+    response: &HitmanResponse,
+    scope: &Scope,
+) -> Result<Vec<CheckResult>> {
+    let table = match scope.assertions() {
+        Some(Value::Table(table)) => table,
+        Some(_) => bail!("Invalid _assert section"),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut checks = Vec::new();
+
+    for (key, value) in table {
+        match key.as_str() {
+            "status" => checks.push(check_status(response, value)?),
+            "max_time" => checks.push(check_time(response, value)?),
+            "headers" => {
+                let Value::Table(headers) = value else {
+                    bail!("Invalid _assert rule: headers must be a table");
+                };
+                for (name, pattern) in headers {
+                    checks.push(check_header(response, name, pattern)?);
+                }
+            }
+            "json" => {
+                let Value::Table(rules) = value else {
+                    bail!("Invalid _assert rule: json must be a table");
+                };
+                let json = response.json();
+                for (path, rule) in rules {
+                    checks.push(check_json(json.as_ref(), path, rule)?);
+                }
+            }
+            _ => bail!("Invalid _assert rule: {key}"),
+        }
+    }
+
+    Ok(checks)
+}
+
+fn check_status(
+    response: &HitmanResponse,
+    expected: &Value,
+) -> Result<CheckResult> {
+    let status = response.status.as_u16();
+
+    let patterns = match expected {
+        Value::Array(arr) => arr.iter().collect(),
+        other => vec![other],
+    };
+
+    let mut matched = false;
+    let mut descriptions = Vec::new();
+    for pattern in patterns {
+        matched |= status_matches(status, pattern)?;
+        descriptions.push(match pattern {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        });
+    }
+
+    Ok(CheckResult::new(
+        format!("status is {}", descriptions.join(" or ")),
+        (!matched).then(|| format!("got {status}")),
+    ))
+}
+
+/// Match a status code against a code (200), a class ("2xx") or a
+/// range ("200-299").
+pub fn status_matches(status: u16, pattern: &Value) -> Result<bool> {
+    match pattern {
+        Value::Integer(code) => Ok(i64::from(status) == *code),
+        Value::String(s) => {
+            let s = s.trim();
+            if let Some((from, to)) = s.split_once('-') {
+                let from: u16 = from.trim().parse()?;
+                let to: u16 = to.trim().parse()?;
+                Ok((from..=to).contains(&status))
+            } else if let Some(class) = s.to_lowercase().strip_suffix("xx") {
+                Ok(status / 100 == class.parse::<u16>()?)
+            } else {
+                Ok(status == s.parse::<u16>()?)
+            }
+        }
+        other => bail!("Invalid status: {other}"),
+    }
+}
+
+fn check_time(
+    response: &HitmanResponse,
+    expected: &Value,
+) -> Result<CheckResult> {
+    let Value::Integer(millis) = expected else {
+        bail!("Invalid _assert rule: max_time must be milliseconds");
+    };
+    let max = Duration::from_millis(u64::try_from(*millis)?);
+
+    Ok(CheckResult::new(
+        format!("response time is at most {max:.2?}"),
+        (response.elapsed > max)
+            .then(|| format!("took {:.2?}", response.elapsed)),
+    ))
+}
+
+fn check_header(
+    response: &HitmanResponse,
+    name: &str,
+    pattern: &Value,
+) -> Result<CheckResult> {
+    let Value::String(pattern) = pattern else {
+        bail!("Invalid _assert rule for header {name}");
+    };
+    let regex = Regex::new(pattern)?;
+
+    let failure = match response.headers.get(name) {
+        None => Some("header not found".to_string()),
+        Some(value) => {
+            let value = value.to_str().unwrap_or_default();
+            (!regex.is_match(value)).then(|| format!("got '{value}'"))
+        }
+    };
+
+    Ok(CheckResult::new(
+        format!("header {name} matches '{pattern}'"),
+        failure,
+    ))
+}
+
+fn check_json(
+    json: Option<&JsonValue>,
+    path: &str,
+    rule: &Value,
+) -> Result<CheckResult> {
+    let jsonpath = JsonPath::parse(path)
+        .map_err(|err| anyhow!("Invalid jsonpath: {err}"))?;
+    let found = json.and_then(|json| jsonpath.query(json).first());
+
+    let (description, failure) = match rule {
+        Value::Table(t) => check_json_rule(path, found, t)?,
+        expected => {
+            let expected = serde_json::to_value(expected)?;
+            let failure = match found {
+                None => Some("not found".to_string()),
+                Some(v) if *v == expected => None,
+                Some(v) => Some(format!("got {v}")),
+            };
+            (format!("{path} equals {expected}"), failure)
+        }
+    };
+
+    Ok(CheckResult::new(description, failure))
+}
+
+fn check_json_rule(
+    path: &str,
+    found: Option<&JsonValue>,
+    rule: &Table,
+) -> Result<(String, Option<String>)> {
+    if let Some(Value::Boolean(exists)) = rule.get("exists") {
+        let failure = match (found, exists) {
+            (Some(v), false) => Some(format!("got {v}")),
+            (None, true) => Some("not found".to_string()),
+            _ => None,
+        };
+        let verb = if *exists { "exists" } else { "does not exist" };
+        return Ok((format!("{path} {verb}"), failure));
+    }
+
+    if let Some(Value::String(pattern)) = rule.get("regex") {
+        let regex = Regex::new(pattern)?;
+        let failure = match found {
+            None => Some("not found".to_string()),
+            Some(JsonValue::String(s)) if regex.is_match(s) => None,
+            Some(v) if !v.is_string() && regex.is_match(&v.to_string()) => None,
+            Some(v) => Some(format!("got {v}")),
+        };
+        return Ok((format!("{path} matches '{pattern}'"), failure));
+    }
+
+    if let Some(expected) = rule.get("equals") {
+        let expected = serde_json::to_value(expected)?;
+        let failure = match found {
+            None => Some("not found".to_string()),
+            Some(v) if *v == expected => None,
+            Some(v) => Some(format!("got {v}")),
+        };
+        return Ok((format!("{path} equals {expected}"), failure));
+    }
+
+    bail!("Invalid _assert rule for {path}")
+}
+
+pub fn print_checks(checks: &[CheckResult]) {
+    for check in checks {
+        let line = match &check.failure {
+            None => format!("# ok: {}", check.description),
+            Some(failure) => {
+                format!("# FAILED: {} ({failure})", check.description)
+            }
+        };
+        warn!("{}", truncate(&line));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{header::HeaderMap, StatusCode};
+
+    use super::*;
+
+    fn response(status: u16, body: &str) -> HitmanResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+
+        HitmanResponse {
+            status: StatusCode::from_u16(status).unwrap(),
+            headers,
+            body: body.to_string(),
+            elapsed: Duration::from_millis(100),
+        }
+    }
+
+    fn scope(s: &str) -> Scope {
+        toml::from_str::<Table>(s).unwrap().into()
+    }
+
+    #[test]
+    fn matches_status_patterns() {
+        assert!(status_matches(201, &Value::Integer(201)).unwrap());
+        assert!(status_matches(201, &Value::from("2xx")).unwrap());
+        assert!(status_matches(201, &Value::from("200-299")).unwrap());
+        assert!(!status_matches(404, &Value::from("2xx")).unwrap());
+        assert!(status_matches(404, &Value::from("404")).unwrap());
+    }
+
+    #[test]
+    fn checks_response() {
+        let scope = scope(
+            r#"
+            [_assert]
+            status = [200, 201]
+            max_time = 50
+
+            [_assert.headers]
+            content-type = "json"
+
+            [_assert.json]
+            "$.id" = 1
+            "$.name" = { regex = "^Gr" }
+            "$.missing" = { exists = false }
+            "#,
+        );
+
+        let checks =
+            check_response(&response(200, r#"{"id":1,"name":"Red"}"#), &scope)
+                .unwrap();
+
+        let failed: Vec<_> = checks
+            .iter()
+            .filter(|c| !c.passed())
+            .map(|c| c.description.as_str())
+            .collect();
+
+        assert_eq!(checks.len(), 6);
+        assert_eq!(
+            failed,
+            vec!["$.name matches '^Gr'", "response time is at most 50.00ms"]
+        );
+    }
+
+    #[test]
+    fn fails_for_invalid_rules() {
+        let scope = scope(
+            r#"
+            [_assert]
+            status_code = 200
+            "#,
+        );
+
+        assert!(check_response(&response(200, "{}"), &scope).is_err());
+    }
+}
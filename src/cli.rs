@@ -1,9 +1,18 @@
 use anyhow::{bail, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The name of a request file to execute and exit.
     /// Omit this argument to run an interactive prompt.
     pub name: Option<String>,
@@ -30,17 +39,22 @@ pub struct Args {
     pub select: Option<Option<String>>,
 
     /// Target to run a request against
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub target: Option<String>,
 
     /// Show more output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
 
     /// Show no output except the returned data
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub quiet: bool,
 
+    /// Show secrets in the output, and store them in the history, instead
+    /// of masking them
+    #[arg(long, global = true)]
+    pub show_secrets: bool,
+
     /// Do not ask questions
     #[arg(short, long, requires = "name")]
     pub non_interactive: bool,
@@ -69,6 +83,113 @@ pub struct Args {
     /// Watch file for changes (implies non-interactove).
     #[arg(short, long, requires = "name", conflicts_with = "flurry")]
     pub watch: bool,
+
+    /// Compare the response with a stored snapshot, or store one if it
+    /// doesn't exist.
+    #[arg(long, requires = "name", conflicts_with = "flurry")]
+    pub snapshot: bool,
+
+    /// Store the response as the new snapshot.
+    #[arg(long, requires = "name", conflicts_with = "flurry")]
+    pub accept: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run all requests that have an `_assert` or `_snapshot` section, and
+    /// check the responses. Exits with an error if any check fails.
+    Test {
+        /// Request files, flows or directories to test.
+        /// Defaults to the current directory.
+        paths: Vec<String>,
+
+        /// Write a JUnit XML report to the given file
+        #[arg(long)]
+        junit: Option<String>,
+
+        /// Write a TAP report to the given file
+        #[arg(long)]
+        tap: Option<String>,
+
+        /// Store the responses as new snapshots
+        #[arg(long)]
+        accept: bool,
+    },
+
+    /// List stored responses, newest first. Use --show or --resend with the
+    /// number of an entry.
+    History {
+        /// Only list the history of this request
+        name: Option<String>,
+
+        /// Show the entry with the given number
+        #[arg(long, conflicts_with = "resend")]
+        show: Option<usize>,
+
+        /// Send the request of the entry with the given number again
+        #[arg(long)]
+        resend: Option<usize>,
+    },
+
+    /// Apply the `_extract` rules to the latest stored response of a
+    /// request, without sending it again, and update the data file.
+    Extract {
+        /// The name of the request file
+        name: String,
+
+        /// Only show the values that would be extracted
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Send the same request to two targets, and show the differences
+    /// between the responses.
+    Diff {
+        /// The name of the request file
+        name: String,
+
+        /// The first target
+        #[arg(value_name = "TARGET")]
+        left: String,
+
+        /// The second target
+        #[arg(value_name = "TARGET")]
+        right: String,
+
+        /// Optional Name=Value pairs to substitute in the request.
+        #[arg(value_parser = parse_key_val)]
+        options: Vec<(String, String)>,
+
+        /// JSONPath of values to ignore in the response body
+        #[arg(long)]
+        ignore: Vec<String>,
+
+        /// Show the responses side by side, instead of a unified diff
+        #[arg(short, long)]
+        side_by_side: bool,
+    },
+
+    /// Manage the encrypted secrets file, `hitman.secrets.toml.age`.
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SecretsCommand {
+    /// Decrypt the secrets file, open it in $EDITOR, and encrypt it again.
+    Edit,
+
+    /// Add a single value to the secrets file. Use --target to add it to a
+    /// target, instead of globally.
+    Set {
+        /// The name of the value
+        key: String,
+
+        /// The value
+        value: String,
+    },
 }
 
 /// Parse a single key-value pair
@@ -0,0 +1,376 @@
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result};
+use futures::future::try_join;
+use reqwest::cookie::Jar;
+use similar::{DiffTag, TextDiff};
+use toml::Value;
+
+use crate::{
+    env::load_env,
+    prompt::{prepare_request_interactive, UserInteraction},
+    request::{
+        build_client_with_cookies, do_request, HitmanRequest, HitmanResponse,
+    },
+    resolve::Resolved,
+    snapshot::Normalization,
+};
+
+/// The normalized responses to the same request, sent to two targets.
+pub struct Comparison {
+    pub targets: [String; 2],
+    pub responses: [String; 2],
+}
+
+/// A line in a side-by-side view of a comparison.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Row {
+    Same(String),
+    Changed {
+        left: Option<String>,
+        right: Option<String>,
+    },
+}
+
+/// Prepare the request once for each target, and send them concurrently.
+///
+/// Values matching the `ignore` paths are left out of the comparison, in
+/// addition to the ones listed in the `_compare` section.
+pub async fn compare_targets<I>(
+    resolved: &Resolved,
+    targets: [&str; 2],
+    options: &[(String, String)],
+    ignore: &[String],
+    interaction: &I,
+) -> Result<Comparison>
+where
+    I: UserInteraction + ?Sized,
+{
+    let [(left_req, left_norm), (right_req, right_norm)] =
+        prepare_targets(resolved, targets, options, ignore, interaction)?;
+
+    // Each target starts without cookies, the stored ones are not sent
+    let left_client = build_client_with_cookies(Arc::new(Jar::default()))?;
+    let right_client = build_client_with_cookies(Arc::new(Jar::default()))?;
+
+    let (left, right) = try_join(
+        send(&left_client, &left_req, &left_norm),
+        send(&right_client, &right_req, &right_norm),
+    )
+    .await?;
+
+    Ok(Comparison {
+        targets: targets.map(ToString::to_string),
+        responses: [left, right],
+    })
+}
+
+/// Prepare the request for both targets, each with its own values. The user
+/// is asked for a value only once, and both targets get the same answer.
+fn prepare_targets<I>(
+    resolved: &Resolved,
+    targets: [&str; 2],
+    options: &[(String, String)],
+    ignore: &[String],
+    interaction: &I,
+) -> Result<[(HitmanRequest, Normalization); 2]>
+where
+    I: UserInteraction + ?Sized,
+{
+    let interaction = AskOnce {
+        interaction,
+        prompts: RefCell::default(),
+        selections: RefCell::default(),
+    };
+    let [a, b] = targets;
+
+    Ok([
+        prepare(resolved, a, options, ignore, &interaction)?,
+        prepare(resolved, b, options, ignore, &interaction)?,
+    ])
+}
+
+fn prepare<I>(
+    resolved: &Resolved,
+    target: &str,
+    options: &[(String, String)],
+    ignore: &[String],
+    interaction: &I,
+) -> Result<(HitmanRequest, Normalization)>
+where
+    I: UserInteraction + ?Sized,
+{
+    let scope = load_env(target, resolved, options)?;
+    let req = prepare_request_interactive(resolved, &scope, interaction)
+        .with_context(|| format!("When preparing request for {target}"))?;
+
+    let mut normalization =
+        Normalization::parse("_compare", scope.comparison())?;
+    normalization.ignore.extend_from_slice(ignore);
+
+    Ok((req, normalization))
+}
+
+/// The choices that were offered, and what was selected.
+type Selection = (Vec<Value>, Vec<String>);
+
+/// Remembers the answers of the user. A selection is only reused when the
+/// same choices are offered again, since lists can differ per target.
+struct AskOnce<'a, I: ?Sized> {
+    interaction: &'a I,
+    prompts: RefCell<HashMap<String, String>>,
+    selections: RefCell<HashMap<String, Selection>>,
+}
+
+impl<I> AskOnce<'_, I>
+where
+    I: UserInteraction + ?Sized,
+{
+    fn select_once(
+        &self,
+        key: &str,
+        values: &[Value],
+        select: impl FnOnce() -> Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        if let Some((choices, selected)) = self.selections.borrow().get(key) {
+            if choices == values {
+                return Ok(selected.clone());
+            }
+        }
+
+        let selected = select()?;
+        self.selections
+            .borrow_mut()
+            .insert(key.to_string(), (values.to_vec(), selected.clone()));
+
+        Ok(selected)
+    }
+}
+
+impl<I> UserInteraction for AskOnce<'_, I>
+where
+    I: UserInteraction + ?Sized,
+{
+    fn prompt(&self, key: &str, fallback: Option<&str>) -> Result<String> {
+        if let Some(answer) = self.prompts.borrow().get(key) {
+            return Ok(answer.clone());
+        }
+
+        let answer = self.interaction.prompt(key, fallback)?;
+        self.prompts
+            .borrow_mut()
+            .insert(key.to_string(), answer.clone());
+
+        Ok(answer)
+    }
+
+    fn select(&self, key: &str, values: &[Value]) -> Result<String> {
+        let selected = self.select_once(key, values, || {
+            Ok(vec![self.interaction.select(key, values)?])
+        })?;
+
+        Ok(selected.into_iter().next().unwrap_or_default())
+    }
+
+    fn select_multiple(
+        &self,
+        key: &str,
+        values: &[Value],
+    ) -> Result<Vec<String>> {
+        self.select_once(key, values, || {
+            self.interaction.select_multiple(key, values)
+        })
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    req: &HitmanRequest,
+    normalization: &Normalization,
+) -> Result<String> {
+    let (res, elapsed) = do_request(client, req).await?;
+    let response = HitmanResponse::receive(res, elapsed).await?;
+    let value = normalization.apply(&response)?;
+
+    Ok(serde_json::to_string_pretty(&value)? + "\n")
+}
+
+impl Comparison {
+    pub fn is_identical(&self) -> bool {
+        self.responses[0] == self.responses[1]
+    }
+
+    pub fn unified(&self) -> String {
+        TextDiff::from_lines(&self.responses[0], &self.responses[1])
+            .unified_diff()
+            .header(&self.targets[0], &self.targets[1])
+            .to_string()
+    }
+
+    pub fn rows(&self) -> Vec<Row> {
+        let [left, right] = &self.responses;
+        let old: Vec<&str> = left.lines().collect();
+        let new: Vec<&str> = right.lines().collect();
+
+        let mut rows = Vec::new();
+        for op in TextDiff::from_lines(left, right).ops() {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                rows.extend(
+                    old[old_range].iter().map(|s| Row::Same(s.to_string())),
+                );
+                continue;
+            }
+
+            let old_lines = &old[old_range];
+            let new_lines = &new[new_range];
+            for i in 0..old_lines.len().max(new_lines.len()) {
+                rows.push(Row::Changed {
+                    left: old_lines.get(i).map(ToString::to_string),
+                    right: new_lines.get(i).map(ToString::to_string),
+                });
+            }
+        }
+
+        rows
+    }
+
+    /// Format the rows in two columns, marking the differences like `sdiff`.
+    pub fn side_by_side(&self) -> Vec<String> {
+        let rows = self.rows();
+        let width = rows
+            .iter()
+            .map(|row| match row {
+                Row::Same(s) => s.chars().count(),
+                Row::Changed { left, .. } => {
+                    left.as_ref().map_or(0, |s| s.chars().count())
+                }
+            })
+            .max()
+            .unwrap_or(0)
+            .max(self.targets[0].len());
+
+        let header =
+            format!("{:width$}   {}", self.targets[0], self.targets[1]);
+
+        [header]
+            .into_iter()
+            .chain(rows.iter().map(|row| match row {
+                Row::Same(s) => format!("{s:width$}   {s}"),
+                Row::Changed { left, right } => {
+                    let marker = match (left, right) {
+                        (Some(_), Some(_)) => '|',
+                        (Some(_), None) => '<',
+                        _ => '>',
+                    };
+                    format!(
+                        "{:width$} {marker} {}",
+                        left.as_deref().unwrap_or_default(),
+                        right.as_deref().unwrap_or_default()
+                    )
+                }
+            }))
+            .map(|line| line.trim_end().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, fs};
+
+    use mktemp::Temp;
+
+    use crate::resolve::resolve_path;
+
+    use super::*;
+
+    /// Answers every prompt with the same value, and counts the prompts.
+    struct CountingInteraction(Cell<usize>);
+
+    impl UserInteraction for CountingInteraction {
+        fn prompt(&self, _: &str, _: Option<&str>) -> Result<String> {
+            self.0.set(self.0.get() + 1);
+            Ok("42".to_string())
+        }
+
+        fn select(&self, _: &str, _: &[Value]) -> Result<String> {
+            unimplemented!()
+        }
+
+        fn select_multiple(&self, _: &str, _: &[Value]) -> Result<Vec<String>> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn prepares_requests_per_target_and_asks_once() {
+        let tmp = Temp::new_dir().unwrap();
+        let config = r#"
+            [staging]
+            base_url = "http://staging"
+
+            [production]
+            base_url = "http://production"
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(
+            tmp.join("get.http"),
+            "GET {{base_url}}/apples/{{id}} HTTP/1.1\n\n",
+        )
+        .unwrap();
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+
+        let interaction = CountingInteraction(Cell::new(0));
+        let [(left, _), (right, _)] = prepare_targets(
+            &resolved,
+            ["staging", "production"],
+            &[],
+            &[],
+            &interaction,
+        )
+        .unwrap();
+
+        assert_eq!(left.url.as_str(), "http://staging/apples/42");
+        assert_eq!(right.url.as_str(), "http://production/apples/42");
+        assert_eq!(interaction.0.get(), 1);
+    }
+
+    fn comparison(left: &str, right: &str) -> Comparison {
+        Comparison {
+            targets: ["staging".into(), "production".into()],
+            responses: [left.into(), right.into()],
+        }
+    }
+
+    #[test]
+    fn aligns_rows() {
+        let c = comparison("a\nb\nc\n", "a\nx\nc\nd\n");
+
+        assert_eq!(
+            c.rows(),
+            vec![
+                Row::Same("a".into()),
+                Row::Changed {
+                    left: Some("b".into()),
+                    right: Some("x".into())
+                },
+                Row::Same("c".into()),
+                Row::Changed {
+                    left: None,
+                    right: Some("d".into())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_side_by_side() {
+        let c = comparison("a\nb\n", "a\n");
+
+        assert_eq!(
+            c.side_by_side(),
+            vec!["staging   production", "a         a", "b       <"]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Duration, Local, SecondsFormat, Utc};
+use uuid::Uuid;
+
+use crate::external::env_var;
+
+pub fn is_dynamic(key: &str) -> bool {
+    key.starts_with('$')
+}
+
+/// Generate a value, like `$uuid` or `$date +7d %Y-%m-%d`, or read an
+/// environment variable, like `$env.API_TOKEN`.
+///
+/// This is done once per request, so that the same placeholder gives the
+/// same value everywhere in a request.
+pub fn generate(key: &str) -> Result<String> {
+    let mut args = key.split_whitespace();
+    let name = args.next().unwrap_or_default();
+    let args: Vec<&str> = args.collect();
+
+    if let Some(var) = name.strip_prefix("$env.") {
+        return env_var(var);
+    }
+
+    let value = match (name, args.as_slice()) {
+        ("$uuid", []) => Uuid::new_v4().to_string(),
+        ("$timestamp", []) => Utc::now().timestamp().to_string(),
+        ("$isoTimestamp", []) => {
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+        }
+        ("$randomInt", args) => {
+            let (min, max) = match args {
+                [] => (0, 1000),
+                [min, max] => (parse_int(min)?, parse_int(max)?),
+                _ => bail!("Usage: {{{{$randomInt MIN MAX}}}}"),
+            };
+            if min > max {
+                bail!("Invalid range for $randomInt: {min} > {max}");
+            }
+            fastrand::i64(min..=max).to_string()
+        }
+        ("$randomString", args) => {
+            let len = match args {
+                [] => 16,
+                [len] => len
+                    .parse()
+                    .with_context(|| format!("Invalid length: {len}"))?,
+                _ => bail!("Usage: {{{{$randomString LENGTH}}}}"),
+            };
+            (0..len).map(|_| fastrand::alphanumeric()).collect()
+        }
+        ("$date", args) => {
+            let (offset, format) = match args {
+                [offset, rest @ ..] if offset.starts_with(['+', '-']) => {
+                    (parse_offset(offset)?, rest.join(" "))
+                }
+                rest => (Duration::zero(), rest.join(" ")),
+            };
+            let format = if format.is_empty() {
+                "%Y-%m-%d"
+            } else {
+                &format
+            };
+
+            let mut date = String::new();
+            write!(date, "{}", (Local::now() + offset).format(format))
+                .map_err(|_| anyhow!("Invalid date format: {format}"))?;
+            date
+        }
+        _ => bail!("Unknown dynamic variable: {key}"),
+    };
+
+    Ok(value)
+}
+
+fn parse_int(s: &str) -> Result<i64> {
+    s.parse().with_context(|| format!("Invalid number: {s}"))
+}
+
+/// Parse a relative time, like `+7d`, `-2h`, `+30m`, `+10s` or `+1w`.
+fn parse_offset(s: &str) -> Result<Duration> {
+    let unit = s.chars().last().unwrap_or_default();
+    let amount = parse_int(&s[..s.len() - unit.len_utf8()])?;
+
+    Ok(match unit {
+        'w' => Duration::weeks(amount),
+        'd' => Duration::days(amount),
+        'h' => Duration::hours(amount),
+        'm' => Duration::minutes(amount),
+        's' => Duration::seconds(amount),
+        _ => bail!("Invalid offset: {s}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_values() {
+        assert_eq!(generate("$uuid").unwrap().len(), 36);
+        assert_eq!(generate("$randomString 12").unwrap().len(), 12);
+        assert_eq!(generate("$randomInt 7 7").unwrap(), "7");
+        assert!(generate("$timestamp").unwrap().parse::<i64>().is_ok());
+        assert!(generate("$isoTimestamp").unwrap().ends_with('Z'));
+
+        assert!(generate("$randomInt 9 1").is_err());
+        assert!(generate("$unknown").is_err());
+    }
+
+    #[test]
+    fn generates_relative_dates() {
+        let week = (Local::now() + Duration::days(7)).format("%d.%m.%Y");
+        assert_eq!(generate("$date +7d %d.%m.%Y").unwrap(), week.to_string());
+
+        let today = Local::now().format("%Y-%m-%d").to_string();
+        assert_eq!(generate("$date").unwrap(), today);
+
+        assert_eq!(parse_offset("-2h").unwrap(), Duration::hours(-2));
+        assert!(parse_offset("+2y").is_err());
+        assert!(generate("$date %Q").is_err());
+    }
+}
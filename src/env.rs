@@ -1,22 +1,32 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use inquire::Select;
-use log::warn;
+use log::{debug, warn};
 use reqwest::cookie::CookieStore;
 use reqwest::Url;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::{self, read_to_string};
 use std::path::{Path, PathBuf};
 use std::string::ToString;
 use toml::{Table as TomlTable, Value};
 use walkdir::WalkDir;
 
+use crate::external::is_external;
+use crate::history::HISTORY_DIR;
 use crate::prompt::fuzzy_match;
+use crate::reference::StoredResponses;
 use crate::resolve::Resolved;
 use crate::scope::Scope;
+use crate::secrets::{read_secrets, without_secrets, SECRETS_FILE};
 
 const CONFIG_FILE: &str = "hitman.toml";
 const LOCAL_CONFIG_FILE: &str = "hitman.local.toml";
 const TARGET_FILE: &str = ".hitman-target";
 const DATA_FILE: &str = ".hitman-data.toml";
+const DIR_CONFIG_FILE: &str = "_dir.toml";
+const DOTENV_KEY: &str = "_dotenv";
+const EXTENDS_KEY: &str = "_extends";
+const HEADERS_KEY: &str = "_headers";
 
 const COOKIE_KEY: &str = "Cookies";
 pub struct HitmanCookieJar {
@@ -72,14 +82,14 @@ impl CookieStore for HitmanCookieJar {
 }
 
 pub fn select_target(root_dir: &Path) -> Result<()> {
-    let items = find_environments(root_dir)?;
+    let items = find_targets(root_dir)?;
 
     let selected = Select::new("Select target", items)
         .with_page_size(15)
         .with_scorer(&|filter, _, value, _| fuzzy_match(filter, value))
         .prompt()?;
 
-    set_target(root_dir, &selected)?;
+    set_target(root_dir, &selected.name)?;
 
     Ok(())
 }
@@ -94,28 +104,152 @@ pub fn set_target(root_dir: &Path, selected: &str) -> Result<()> {
 pub fn find_environments(root_dir: &Path) -> Result<Vec<String>> {
     let config = read_and_merge_config(root_dir)?;
     let keys: Vec<String> = config
-        .keys()
-        .filter(|k| !k.starts_with('_'))
-        .filter(|k| config.get(*k).expect("key must exist").is_table())
-        .map(ToString::to_string)
+        .iter()
+        .filter(|(k, v)| is_target(k, v))
+        .map(|(k, _)| k.to_string())
         .collect();
 
     Ok(keys)
 }
 
+/// Whether a top-level value is a target, and not a value like
+/// `token = { _env = "TOKEN" }`.
+fn is_target(key: &str, value: &Value) -> bool {
+    !key.starts_with('_') && value.as_table().is_some_and(|t| !is_external(t))
+}
+
+/// Whether a top-level value is used for all targets, like plain values,
+/// values from outside, and default headers.
+fn is_global(key: &str, value: &Value) -> bool {
+    match value {
+        Value::Table(t) => key == HEADERS_KEY || is_external(t),
+        _ => true,
+    }
+}
+
+/// A target, and the targets it extends with `_extends`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub name: String,
+
+    /// The targets this one extends, nearest first
+    pub extends: Vec<String>,
+}
+
+impl Display for Target {
+    /// The name, indented by how many targets it extends, so that a sorted
+    /// list shows the hierarchy.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", "  ".repeat(self.extends.len()), self.name)
+    }
+}
+
+/// All targets, with each target listed after the one it extends.
+pub fn find_targets(root_dir: &Path) -> Result<Vec<Target>> {
+    let config = read_and_merge_config(root_dir)?;
+
+    let mut targets = Vec::new();
+    for name in find_environments(root_dir)? {
+        let mut chain = target_chain(&config, &name)?;
+        chain.remove(0);
+        targets.push(Target {
+            name,
+            extends: chain,
+        });
+    }
+
+    // Sort by the path from the outermost target
+    targets.sort_by_cached_key(|t| {
+        let mut path: Vec<&str> =
+            t.extends.iter().rev().map(String::as_str).collect();
+        path.push(&t.name);
+        path.join("\n")
+    });
+
+    Ok(targets)
+}
+
+/// The target, followed by the targets it extends with `_extends`.
+fn target_chain(config: &TomlTable, target: &str) -> Result<Vec<String>> {
+    let mut chain = vec![target.to_string()];
+
+    let mut current = target;
+    while let Some(parent) =
+        config.get(current).and_then(|t| t.get(EXTENDS_KEY))
+    {
+        let Some(parent) = parent.as_str() else {
+            bail!("Invalid {EXTENDS_KEY} option in {current}: {parent}");
+        };
+        if chain.iter().any(|t| t == parent) {
+            bail!("Cyclic {EXTENDS_KEY}: {} -> {parent}", chain.join(" -> "));
+        }
+        if !config.get(parent).is_some_and(|v| is_target(parent, v)) {
+            bail!("Target {current} extends unknown target {parent}");
+        }
+
+        chain.push(parent.to_string());
+        current = parent;
+    }
+
+    Ok(chain)
+}
+
+/// The values of a target, merged with the values of the targets it
+/// extends.
+fn target_table(config: &TomlTable, chain: &[String]) -> Option<TomlTable> {
+    let mut table: Option<TomlTable> = None;
+    for name in chain.iter().rev() {
+        let Some(Value::Table(t)) = config.get(name) else {
+            continue;
+        };
+        add_layer(table.get_or_insert_with(TomlTable::new), t.clone());
+    }
+
+    if let Some(table) = &mut table {
+        table.remove(EXTENDS_KEY);
+    }
+
+    table
+}
+
 /// Get all files to watch for changes in watch mode.
 ///
 /// This includes all files used by the request, except the data file.
-/// Trying to watch the data file just causes loops.
+/// Trying to watch the data file just causes loops. Files that don't exist
+/// can't be watched, and are left out.
 pub fn watch_list(root_dir: &Path, resolved: &Resolved) -> Vec<PathBuf> {
-    vec![
+    let mut files = vec![
         resolved.original_path().to_path_buf(),
         resolved.http_file().to_path_buf(),
         resolved.toml_path(),
         root_dir.join(TARGET_FILE),
         root_dir.join(CONFIG_FILE),
         root_dir.join(LOCAL_CONFIG_FILE),
-    ]
+        root_dir.join(SECRETS_FILE),
+    ];
+    files.extend(dir_config_files(resolved));
+    files.retain(|f| f.exists());
+
+    files
+}
+
+/// The `_dir.toml` files that apply to a request, in the directories
+/// between the root directory and the request, from the root down.
+fn dir_config_files(resolved: &Resolved) -> Vec<PathBuf> {
+    let toml_path = resolved.toml_path();
+    if !toml_path.starts_with(&resolved.root_dir) {
+        return Vec::new();
+    }
+
+    let mut files: Vec<PathBuf> = toml_path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| *dir != &*resolved.root_dir)
+        .map(|dir| dir.join(DIR_CONFIG_FILE))
+        .collect();
+    files.reverse();
+
+    files
 }
 
 pub fn load_env(
@@ -123,36 +257,164 @@ pub fn load_env(
     resolved: &Resolved,
     options: &[(String, String)],
 ) -> Result<Scope> {
-    use Value::Table;
-
     let mut table = TomlTable::new();
 
     let config = read_and_merge_config(&resolved.root_dir)?;
+    let chain = target_chain(&config, target)?;
+    let target_values = target_table(&config, &chain);
+
+    let (mut sources, target_sources) =
+        config_sources(&resolved.root_dir, &chain)?;
+
+    // Global defaults, and default headers
+    table.extend(
+        config
+            .clone()
+            .into_iter()
+            .filter(|(k, v)| is_global(k, v)),
+    );
+
+    // Values from .env files, listed in `_dotenv`
+    let dotenv =
+        read_dotenv_files(&resolved.root_dir, &config, target_values.as_ref())?;
+    for (file, values) in dotenv {
+        note_sources(&mut sources, &values, &file);
+        table.extend(values);
+    }
 
-    // Global defaults
-    table.extend(config.clone().into_iter().filter(|(_, v)| !v.is_table()));
-
-    if let Some(Table(t)) = config.get(target) {
-        table.extend(t.clone());
+    if let Some(t) = target_values {
+        add_layer(&mut table, t);
     }
+    sources.extend(target_sources);
 
     // TODO Handle GQL specifically?
 
-    if let Some(content) = read_toml(&resolved.toml_path())? {
-        table.extend(content);
+    // Values for the directories of the request, and the request itself
+    let toml_path = resolved.toml_path();
+    for path in dir_config_files(resolved).iter().chain([&toml_path]) {
+        if let Some(content) = read_toml(path)? {
+            let name = path.strip_prefix(&resolved.root_dir).unwrap_or(path);
+            note_sources(&mut sources, &content, &name.display().to_string());
+            add_layer(&mut table, content);
+        }
     }
 
     // FIXME state per environment
     if let Some(content) = read_toml(&resolved.root_dir.join(DATA_FILE))? {
+        note_sources(&mut sources, &content, DATA_FILE);
         table.extend(content);
     }
 
     // Extra values passed on the command line
     for (k, v) in options {
         table.insert(k.clone(), Value::String(v.clone()));
+        sources.insert(k.clone(), "command line".into());
+    }
+
+    Ok(Scope::from(table)
+        .with_responses(StoredResponses {
+            root_dir: resolved.root_dir.to_path_buf(),
+            target: target.to_string(),
+        })
+        .with_sources(sources))
+}
+
+/// The config file each value comes from, for global defaults and for the
+/// target, where the local config file takes precedence over the shared one,
+/// and the secrets file over both. Values of the target take precedence over
+/// values of the targets it extends.
+fn config_sources(
+    root_dir: &Path,
+    chain: &[String],
+) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
+    let mut global = HashMap::new();
+    let mut targeted = HashMap::new();
+
+    let files = [
+        (CONFIG_FILE, read_toml(&root_dir.join(CONFIG_FILE))?),
+        (
+            LOCAL_CONFIG_FILE,
+            read_toml(&root_dir.join(LOCAL_CONFIG_FILE))?,
+        ),
+        (SECRETS_FILE, read_secrets(root_dir)?),
+    ];
+    let files: Vec<_> = files
+        .into_iter()
+        .filter_map(|(file, content)| Some((file, content?)))
+        .collect();
+
+    for (file, content) in &files {
+        for (key, value) in content {
+            if is_global(key, value) {
+                global.insert(key.clone(), file.to_string());
+            }
+        }
+    }
+    for name in chain.iter().rev() {
+        for (file, content) in &files {
+            if let Some(Value::Table(t)) = content.get(name) {
+                note_sources(&mut targeted, t, file);
+            }
+        }
+    }
+
+    Ok((global, targeted))
+}
+
+/// Read the `.env` files listed in `_dotenv`, globally and for the target.
+/// Files that don't exist are skipped, since they are often not shared.
+fn read_dotenv_files(
+    root_dir: &Path,
+    config: &TomlTable,
+    target: Option<&TomlTable>,
+) -> Result<Vec<(String, TomlTable)>> {
+    let global = config.get(DOTENV_KEY);
+    let targeted = target.and_then(|t| t.get(DOTENV_KEY));
+
+    let mut files = Vec::new();
+    for list in [global, targeted].into_iter().flatten() {
+        match list {
+            Value::String(file) => files.push(file.as_str()),
+            Value::Array(arr) => {
+                for file in arr {
+                    let Some(file) = file.as_str() else {
+                        bail!("Invalid {DOTENV_KEY} entry: {file}");
+                    };
+                    files.push(file);
+                }
+            }
+            other => bail!("Invalid {DOTENV_KEY} option: {other}"),
+        }
     }
 
-    Ok(table.into())
+    let mut out = Vec::new();
+    for file in files {
+        let path = root_dir.join(file);
+        if !path.exists() {
+            debug!("# Skipped {file}, not found");
+            continue;
+        }
+
+        let mut values = TomlTable::new();
+        for item in dotenvy::from_path_iter(&path)? {
+            let (key, value) =
+                item.with_context(|| format!("When reading {file}"))?;
+            values.insert(key, Value::String(value));
+        }
+        out.push((file.to_string(), values));
+    }
+
+    Ok(out)
+}
+
+fn note_sources(
+    sources: &mut HashMap<String, String>,
+    table: &TomlTable,
+    file: &str,
+) {
+    for key in table.keys() {
+        sources.insert(key.clone(), file.to_string());
+    }
 }
 
 pub fn get_target(root_dir: &Path) -> String {
@@ -165,6 +427,12 @@ pub fn update_data(root_dir: &Path, vars: &TomlTable) -> Result<()> {
         return Ok(());
     }
 
+    // Decrypted secrets must never end up in the data file
+    let vars = without_secrets(vars);
+    if vars.is_empty() {
+        return Ok(());
+    }
+
     let data_file = root_dir.join(DATA_FILE);
 
     let content =
@@ -172,7 +440,7 @@ pub fn update_data(root_dir: &Path, vars: &TomlTable) -> Result<()> {
 
     let mut state = toml::from_str::<TomlTable>(&content).unwrap_or_default();
 
-    state.extend(vars.clone());
+    state.extend(vars);
     fs::write(&data_file, toml::to_string_pretty(&state)?)?;
 
     Ok(())
@@ -189,19 +457,47 @@ fn read_and_merge_config(root_dir: &Path) -> Result<TomlTable> {
         merge(&mut config, local);
     }
 
+    if let Some(secrets) = read_secrets(root_dir)? {
+        merge(&mut config, secrets);
+    }
+
     Ok(config)
 }
 
+/// Add the values of a target, a directory or a request, replacing values
+/// of the layers before it. Only the `_headers` are combined, so that each
+/// layer can add or remove some of them.
+fn add_layer(table: &mut TomlTable, layer: TomlTable) {
+    for (key, value) in layer {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(headers)), Value::Table(more))
+                if key == HEADERS_KEY =>
+            {
+                headers.extend(more);
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
 /// Merge Toml tables recursively, merging child tables into
 /// existing child tables.
-fn merge(config: &mut TomlTable, other: TomlTable) {
+pub fn merge(config: &mut TomlTable, other: TomlTable) {
     other.into_iter().for_each(move |(k, v)| match v {
         Value::Table(t) => {
-            let cur = config.get_mut(&k);
-            if let Some(Value::Table(ref mut ext)) = cur {
-                merge(ext, t);
-            } else {
-                config.insert(k, Value::Table(t));
+            // A value like `{ _env = "TOKEN" }` replaces the other one as a
+            // whole, it's not a table of values
+            match config.get_mut(&k) {
+                Some(Value::Table(ext))
+                    if !is_external(ext) && !is_external(&t) =>
+                {
+                    merge(ext, t);
+                }
+                _ => {
+                    config.insert(k, Value::Table(t));
+                }
             }
         }
         _ => {
@@ -224,6 +520,8 @@ fn read_toml(file_path: &Path) -> Result<Option<TomlTable>> {
 pub fn find_available_requests(cwd: &Path) -> Result<Vec<PathBuf>> {
     let files: Vec<_> = WalkDir::new(cwd)
         .into_iter()
+        // Skip the stored responses
+        .filter_entry(|e| e.depth() == 0 || e.file_name() != HISTORY_DIR)
         .filter_map(Result::ok)
         .filter(|e| {
             e.file_name().to_str().is_some_and(|s| {
@@ -231,7 +529,8 @@ pub fn find_available_requests(cwd: &Path) -> Result<Vec<PathBuf>> {
                 s != "_graphql.http"
                     && (s.to_lowercase().ends_with(".http")
                         || s.to_lowercase().ends_with(".gql")
-                        || s.to_lowercase().ends_with(".graphql"))
+                        || s.to_lowercase().ends_with(".graphql")
+                        || s.to_lowercase().ends_with(".flow.toml"))
             })
         })
         .map(|p| {
@@ -254,6 +553,8 @@ pub fn find_available_requests(cwd: &Path) -> Result<Vec<PathBuf>> {
 mod tests {
     use mktemp::Temp;
 
+    use crate::{resolve::resolve_path, scope::Replacement};
+
     use super::*;
 
     macro_rules! toml {
@@ -285,6 +586,310 @@ mod tests {
         assert_eq!(envs, vec!["bar", "foo"]);
     }
 
+    #[test]
+    fn skips_hidden_directories() {
+        let tmp = Temp::new_dir().unwrap();
+
+        let history = tmp.join(".hitman-history/login.http");
+        fs::create_dir_all(&history).unwrap();
+        fs::write(history.join("1.json"), "{}").unwrap();
+        fs::write(tmp.join("login.http"), "GET /").unwrap();
+
+        let found = find_available_requests(&tmp).unwrap();
+
+        assert_eq!(found, vec![PathBuf::from("login.http")]);
+    }
+
+    #[test]
+    fn loads_dotenv_files() {
+        let tmp = Temp::new_dir().unwrap();
+
+        let config = r#"
+            _dotenv = [".env", ".env.missing"]
+            host = "global"
+            user = "global"
+
+            [staging]
+            _dotenv = ".env.staging"
+            token = "target"
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(tmp.join(".env"), "user=dotenv\ntoken=dotenv\n").unwrap();
+        fs::write(tmp.join(".env.staging"), "export key='a b'\n").unwrap();
+        fs::write(tmp.join("get.http"), "GET /").unwrap();
+
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+        let scope = load_env("staging", &resolved, &[]).unwrap();
+
+        for (key, value, source) in [
+            ("host", "global", "hitman.toml"),
+            ("user", "dotenv", ".env"),
+            ("token", "target", "hitman.toml"),
+            ("key", "a b", ".env.staging"),
+        ] {
+            assert_eq!(
+                scope.lookup(key).unwrap(),
+                Replacement::Value(value.into())
+            );
+            assert_eq!(scope.source(key), Some(source));
+        }
+    }
+
+    #[test]
+    fn merges_secrets_without_storing_them() {
+        use age::{secrecy::ExposeSecret, x25519::Identity};
+
+        use crate::secrets::Key;
+
+        let tmp = Temp::new_dir().unwrap();
+
+        let identity = Identity::generate().to_string();
+        let identity = identity.expose_secret();
+        fs::write(tmp.join("key.txt"), identity).unwrap();
+
+        let secrets = "[staging]\ntoken = 'secret'\n";
+        let key = Key::parse_identities(identity).unwrap();
+        fs::write(tmp.join(SECRETS_FILE), key.encrypt(secrets, &[]).unwrap())
+            .unwrap();
+
+        let config = "[staging]\ntoken = 'shared'\nhost = 'shared'\n";
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(tmp.join("get.http"), "GET /").unwrap();
+
+        // No other test reads the secrets file, and once it's decrypted, the
+        // key is cached
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+        std::env::set_var("HITMAN_AGE_IDENTITY", tmp.join("key.txt"));
+        let scope = load_env("staging", &resolved, &[]);
+        std::env::remove_var("HITMAN_AGE_IDENTITY");
+        let scope = scope.unwrap();
+
+        assert_eq!(
+            scope.lookup("token").unwrap(),
+            Replacement::Value("secret".into())
+        );
+        assert_eq!(scope.source("token"), Some(SECRETS_FILE));
+        assert_eq!(scope.source("host"), Some(CONFIG_FILE));
+
+        update_data(&tmp, &toml!("copy = 'secret'\nid = 'apple'")).unwrap();
+        let data = read_toml(&tmp.join(DATA_FILE)).unwrap().unwrap();
+        assert_eq!(data, toml!("id = 'apple'"));
+    }
+
+    #[test]
+    fn keeps_external_values_global() {
+        let tmp = Temp::new_dir().unwrap();
+
+        let config = r#"
+            token = { _exec = "echo secret" }
+
+            [staging]
+            host = "staging"
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(tmp.join("get.http"), "GET /").unwrap();
+
+        assert_eq!(find_environments(&tmp).unwrap(), vec!["staging"]);
+
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+        let scope = load_env("staging", &resolved, &[]).unwrap();
+        assert_eq!(
+            scope.lookup("token").unwrap(),
+            Replacement::Literal("secret".into())
+        );
+        assert_eq!(scope.source("token"), Some("hitman.toml"));
+    }
+
+    #[test]
+    fn extends_targets() {
+        let tmp = Temp::new_dir().unwrap();
+
+        let config = r#"
+            [staging]
+            host = "staging"
+            region = "none"
+            user = "admin"
+
+            [staging-eu]
+            _extends = "staging"
+            region = "eu"
+
+            [staging-eu-test]
+            _extends = "staging-eu"
+            user = "tester"
+
+            [dev]
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(tmp.join("hitman.local.toml"), "[staging]\nuser = 'me'")
+            .unwrap();
+        fs::write(tmp.join("get.http"), "GET /").unwrap();
+
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+        let scope = load_env("staging-eu-test", &resolved, &[]).unwrap();
+
+        for (key, value, source) in [
+            ("host", "staging", "hitman.toml"),
+            ("region", "eu", "hitman.toml"),
+            ("user", "tester", "hitman.toml"),
+        ] {
+            assert_eq!(
+                scope.lookup(key).unwrap(),
+                Replacement::Value(value.into())
+            );
+            assert_eq!(scope.source(key), Some(source));
+        }
+
+        let scope = load_env("staging-eu", &resolved, &[]).unwrap();
+        assert_eq!(scope.source("user"), Some("hitman.local.toml"));
+
+        let targets = find_targets(&tmp).unwrap();
+        let names: Vec<_> = targets.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            names,
+            vec!["dev", "staging", "  staging-eu", "    staging-eu-test"]
+        );
+        assert_eq!(targets[3].extends, vec!["staging-eu", "staging"]);
+    }
+
+    #[test]
+    fn detects_cyclic_targets() {
+        let config = toml! {
+        r#"
+            [a]
+            _extends = "b"
+
+            [b]
+            _extends = "a"
+
+            [c]
+            _extends = "missing"
+        "#
+        };
+
+        let err = target_chain(&config, "a").unwrap_err();
+        assert_eq!(err.to_string(), "Cyclic _extends: a -> b -> a");
+        assert!(target_chain(&config, "c").is_err());
+    }
+
+    #[test]
+    fn loads_directory_config() {
+        let tmp = Temp::new_dir().unwrap();
+        let dir = tmp.join("apple/green");
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(tmp.join("hitman.toml"), "[default]\nhost = 'root'").unwrap();
+        fs::write(
+            tmp.join("apple/_dir.toml"),
+            "host = 'apple'\nkind = 'apple'\n_extract = { token = '$.t' }",
+        )
+        .unwrap();
+        fs::write(dir.join("_dir.toml"), "kind = 'green'").unwrap();
+        fs::write(dir.join("get.http"), "GET /").unwrap();
+        fs::write(dir.join("get.http.toml"), "_extract = { id = '$.id' }")
+            .unwrap();
+
+        let resolved = resolve_path(&dir.join("get.http")).unwrap();
+        let scope = load_env("default", &resolved, &[]).unwrap();
+
+        for (key, value, source) in [
+            ("host", "apple", "apple/_dir.toml"),
+            ("kind", "green", "apple/green/_dir.toml"),
+        ] {
+            assert_eq!(
+                scope.lookup(key).unwrap(),
+                Replacement::Value(value.into())
+            );
+            assert_eq!(scope.source(key), Some(source));
+        }
+
+        let extract = scope.extract().unwrap().as_table().unwrap();
+        assert_eq!(extract.keys().collect::<Vec<_>>(), vec!["id"]);
+
+        let watched = watch_list(&resolved.root_dir, &resolved);
+        assert!(watched.contains(&tmp.join("apple/_dir.toml")));
+        assert!(watched.contains(&dir.join("_dir.toml")));
+        assert!(!watched.contains(&tmp.join("hitman.local.toml")));
+    }
+
+    #[test]
+    fn replaces_sections_of_earlier_layers() {
+        let tmp = Temp::new_dir().unwrap();
+
+        let config = r#"
+            [_extract]
+            global = "$.global"
+
+            [_headers]
+            X-Global = "global"
+
+            [default]
+            _extract = { target = "$.target" }
+            _headers = { X-Target = "target" }
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(tmp.join("get.http"), "GET /").unwrap();
+        fs::write(
+            tmp.join("get.http.toml"),
+            "_extract = { id = '$.id' }
+_headers = { X-Request = 'request' }",
+        )
+        .unwrap();
+
+        let resolved = resolve_path(&tmp.join("get.http")).unwrap();
+        let scope = load_env("default", &resolved, &[]).unwrap();
+
+        let extract = scope.extract().unwrap().as_table().unwrap();
+        assert_eq!(extract.keys().collect::<Vec<_>>(), vec!["id"]);
+
+        let headers = scope.headers().unwrap().as_table().unwrap();
+        assert_eq!(
+            headers.keys().collect::<Vec<_>>(),
+            vec!["X-Global", "X-Request", "X-Target"]
+        );
+    }
+
+    #[test]
+    fn adds_default_headers() {
+        use crate::prompt::{prepare_request_interactive, NoUserInteraction};
+
+        let tmp = Temp::new_dir().unwrap();
+        let dir = tmp.join("apple");
+        fs::create_dir_all(&dir).unwrap();
+
+        let config = r#"
+            [_headers]
+            Accept = "application/json"
+            X-Trace = "trace"
+            X-Tenant = "none"
+
+            [default]
+            tenant = "apple"
+            _headers = { X-Tenant = "{{tenant}}" }
+        "#;
+        fs::write(tmp.join("hitman.toml"), config).unwrap();
+        fs::write(dir.join("_dir.toml"), "_headers = { X-Trace = false }")
+            .unwrap();
+        fs::write(
+            dir.join("get.http"),
+            "GET http://localhost/apples HTTP/1.1\nAccept: text/plain\n\n",
+        )
+        .unwrap();
+
+        let resolved = resolve_path(&dir.join("get.http")).unwrap();
+        let scope = load_env("default", &resolved, &[]).unwrap();
+        let req =
+            prepare_request_interactive(&resolved, &scope, &NoUserInteraction)
+                .unwrap();
+
+        assert_eq!(
+            req.to_string(),
+            "GET http://localhost/apples\n\
+            accept: text/plain\n\
+            x-tenant: apple  # default\n\n"
+        );
+    }
+
     #[test]
     fn merges_mested_tables() {
         let shared = toml! {
@@ -328,4 +933,32 @@ mod tests {
 
         assert_eq!(merged, expected);
     }
+
+    #[test]
+    fn replaces_external_values_when_merging() {
+        let mut merged = toml! {
+        r#"
+            token = { _env = "TOKEN" }
+            password = { _exec = "pass show api" }
+        "#
+        };
+        merge(
+            &mut merged,
+            toml! {
+            r#"
+                token = { _exec = "pass show token" }
+                password = "local"
+            "#
+            },
+        );
+
+        let expected = toml! {
+        r#"
+            token = { _exec = "pass show token" }
+            password = "local"
+        "#
+        };
+
+        assert_eq!(merged, expected);
+    }
 }
@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    env,
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use toml::{Table, Value};
+
+/// The key of a value from an environment variable, like
+/// `{ _env = "API_TOKEN" }`.
+const ENV_KEY: &str = "_env";
+
+/// The key of a value from the output of a command, like
+/// `{ _exec = "pass show api/token" }`.
+const EXEC_KEY: &str = "_exec";
+
+/// A value that comes from outside of the config files, like
+/// `{ _env = "API_TOKEN" }` or `{ _exec = "pass show api/token" }`.
+///
+/// Returns `None` if the table is a regular table.
+pub fn external_value(table: &Table) -> Option<Result<String>> {
+    if !is_external(table) {
+        return None;
+    }
+
+    Some(match source(table) {
+        Ok(Source::Env(name)) => env_var(name),
+        Ok(Source::Exec(cmd)) => exec(cmd),
+        Err(err) => Err(err),
+    })
+}
+
+/// Whether a table refers to an external value, without reading it.
+pub fn is_external(table: &Table) -> bool {
+    table.contains_key(ENV_KEY) || table.contains_key(EXEC_KEY)
+}
+
+/// The value of an external value, if it can be read without running a
+/// command, because it's an environment variable or the command already ran.
+pub fn read_value(table: &Table) -> Option<String> {
+    if !is_external(table) {
+        return None;
+    }
+
+    match source(table).ok()? {
+        Source::Env(name) => env::var(name).ok(),
+        Source::Exec(cmd) => {
+            exec_cache().lock().expect("not poisoned").get(cmd).cloned()
+        }
+    }
+}
+
+enum Source<'a> {
+    Env(&'a str),
+    Exec(&'a str),
+}
+
+/// Where an external value comes from. A table with other keys as well is
+/// rejected, since it's unclear whether it's meant as a regular table.
+fn source(table: &Table) -> Result<Source<'_>> {
+    match (table.len(), table.iter().next()) {
+        (1, Some((key, Value::String(name)))) if key == ENV_KEY => {
+            Ok(Source::Env(name))
+        }
+        (1, Some((key, Value::String(cmd)))) if key == EXEC_KEY => {
+            Ok(Source::Exec(cmd))
+        }
+        _ => bail!(
+            "Invalid external value, expected only {ENV_KEY} or {EXEC_KEY} \
+            with a text: {{ {} }}",
+            table.to_string().trim().replace('\n', ", ")
+        ),
+    }
+}
+
+pub fn env_var(name: &str) -> Result<String> {
+    env::var(name)
+        .with_context(|| format!("Environment variable not set: {name}"))
+}
+
+/// Run a command, and use the output as value. The output is cached, so
+/// that each command runs at most once.
+pub fn exec(cmd: &str) -> Result<String> {
+    let cache = exec_cache();
+    if let Some(output) = cache.lock().expect("not poisoned").get(cmd) {
+        return Ok(output.clone());
+    }
+
+    debug!("# Running {cmd}");
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", cmd]).output()
+    } else {
+        Command::new("sh").args(["-c", cmd]).output()
+    }
+    .with_context(|| format!("When running {cmd}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut msg = format!("Command failed ({}): {cmd}", output.status);
+        if !stderr.trim().is_empty() {
+            msg = format!("{msg}\n{}", stderr.trim());
+        }
+        bail!(msg);
+    }
+
+    let value = String::from_utf8(output.stdout)
+        .with_context(|| format!("Output is not text: {cmd}"))?
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+
+    cache
+        .lock()
+        .expect("not poisoned")
+        .insert(cmd.to_string(), value.clone());
+
+    Ok(value)
+}
+
+fn exec_cache() -> &'static Mutex<HashMap<String, String>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(s: &str) -> Table {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        // Only this test uses these variables
+        env::set_var("HITMAN_EXTERNAL_TEST_TOKEN", "abc");
+        let value =
+            external_value(&table("_env = 'HITMAN_EXTERNAL_TEST_TOKEN'"));
+        env::remove_var("HITMAN_EXTERNAL_TEST_TOKEN");
+        assert_eq!(value.unwrap().unwrap(), "abc");
+
+        let value =
+            external_value(&table("_env = 'HITMAN_EXTERNAL_TEST_MISSING'"));
+        assert!(value.unwrap().is_err());
+    }
+
+    #[test]
+    fn runs_commands() {
+        let value = external_value(&table("_exec = 'echo apple'"));
+        assert_eq!(value.unwrap().unwrap(), "apple");
+
+        assert!(external_value(&table("_exec = 'exit 3'")).unwrap().is_err());
+    }
+
+    #[test]
+    fn rejects_ambiguous_tables() {
+        assert!(external_value(&table("env = 'prod'")).is_none());
+        assert!(external_value(&table("exec = 'a'\nenv = 'b'")).is_none());
+
+        let err = external_value(&table("_exec = 'a'\nenv = 'b'"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid external value, expected only _env or _exec with a \
+            text: { _exec = \"a\", env = \"b\" }"
+        );
+        assert!(external_value(&table("_env = 1")).unwrap().is_err());
+    }
+}
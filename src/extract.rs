@@ -1,90 +1,252 @@
-use anyhow::{anyhow, bail, Result};
-use log::info;
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
 use toml::{Table, Value};
 
-use crate::{scope::Scope, util::truncate};
-use jsonpath::Selector;
+use crate::{
+    assertion::status_matches,
+    env::load_env,
+    history::latest_response,
+    redact::MASK,
+    request::HitmanResponse,
+    resolve::Resolved,
+    scope::Scope,
+    transform::{apply_chain, Transform},
+    util::truncate,
+    xml::XmlDocument,
+};
+use regex::Regex;
+use reqwest::header::{HeaderMap, SET_COOKIE};
 use serde_json::Value as JsonValue;
+use serde_json_path::JsonPath;
 
-pub fn extract_variables(data: &JsonValue, scope: &Scope) -> Result<Table> {
+/// The response body, parsed according to its content.
+enum Body {
+    Json(JsonValue),
+    Xml(Box<XmlDocument>),
+    Text,
+}
+
+impl Body {
+    fn parse(response: &HitmanResponse) -> Self {
+        if let Some(json) = response.json() {
+            return Self::Json(json);
+        }
+
+        if !response.is_xml() {
+            return Self::Text;
+        }
+
+        match XmlDocument::parse(&response.body) {
+            Ok(doc) => Self::Xml(Box::new(doc)),
+            Err(_) => Self::Text,
+        }
+    }
+}
+
+/// Conditions for extracting values, from the `_extract_when` section.
+struct ExtractWhen {
+    /// Status codes or ranges, or `None` to extract regardless of status
+    status: Option<Value>,
+
+    /// A JSONPath that must match something in the response body
+    json: Option<String>,
+}
+
+impl ExtractWhen {
+    fn from_scope(scope: &Scope) -> Result<Self> {
+        let mut when = Self {
+            status: Some(Value::from("2xx")),
+            json: None,
+        };
+
+        match scope.extract_when() {
+            None => (),
+            Some(Value::Table(table)) => {
+                for (key, value) in table {
+                    match (key.as_str(), value) {
+                        ("status", value) => when.status = status_rule(value),
+                        ("json", Value::String(path)) => {
+                            when.json = Some(path.clone());
+                        }
+                        _ => bail!("Invalid _extract_when option: {key}"),
+                    }
+                }
+            }
+            Some(_) => bail!("Invalid _extract_when section"),
+        }
+
+        Ok(when)
+    }
+
+    /// The reason for skipping extraction, if the body doesn't match.
+    fn check_body(&self, body: &Body) -> Result<Option<String>> {
+        let Some(path) = &self.json else {
+            return Ok(None);
+        };
+
+        let matched = match body {
+            Body::Json(data) => !make_selector(path)?.query(data).is_empty(),
+            Body::Xml(_) | Body::Text => false,
+        };
+
+        Ok((!matched).then(|| format!("{path} did not match")))
+    }
+}
+
+/// Status patterns, where "any" means that all codes match.
+fn status_rule(value: &Value) -> Option<Value> {
+    match value {
+        Value::String(s) if s.trim() == "any" => None,
+        other => Some(other.clone()),
+    }
+}
+
+fn matches_status(status: u16, patterns: Option<&Value>) -> Result<bool> {
+    match patterns {
+        None => Ok(true),
+        Some(Value::Array(arr)) => {
+            for pattern in arr {
+                if status_matches(status, pattern)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        Some(pattern) => status_matches(status, pattern),
+    }
+}
+
+/// A rule for a single value, which is either just the expression, or a
+/// table like `{ path = "$.error", status = "4xx", transform = "lowercase" }`.
+struct SingleRule<'a> {
+    expr: &'a str,
+    status: Option<Option<Value>>,
+    transforms: Vec<Transform>,
+}
+
+impl SingleRule<'_> {
+    fn is_format(&self) -> bool {
+        self.transforms.iter().any(Transform::is_format)
+    }
+}
+
+fn single_rule(value: &Value) -> Result<Option<SingleRule<'_>>> {
+    match value {
+        Value::String(expr) => Ok(Some(SingleRule {
+            expr,
+            status: None,
+            transforms: Vec::new(),
+        })),
+        // A list rule has a `_` key, and its fields can be named anything
+        Value::Table(conf)
+            if conf.contains_key("path") && !conf.contains_key("_") =>
+        {
+            let Some(Value::String(expr)) = conf.get("path") else {
+                bail!("Invalid _extract rule: {value}");
+            };
+            let transforms = match conf.get("transform") {
+                Some(chain) => Transform::parse_chain(chain)?,
+                None => Vec::new(),
+            };
+            Ok(Some(SingleRule {
+                expr,
+                status: conf.get("status").map(status_rule),
+                transforms,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn extract_variables(
+    response: &HitmanResponse,
+    scope: &Scope,
+) -> Result<Table> {
     let mut out = Table::new();
+    let body = Body::parse(response);
+    let status = response.status.as_u16();
+
+    let when = ExtractWhen::from_scope(scope)?;
+    let body_mismatch = when.check_body(&body)?;
 
     match scope.extract() {
         Some(Value::Table(table)) => {
-            for (key, value) in table {
-                match value {
-                    Value::String(jsonpath) => {
-                        let selector = make_selector(jsonpath)?;
-
-                        if let Some(JsonValue::String(val)) =
-                            selector.find(data).next()
-                        {
-                            let msg = format!("# Got string '{key}' = '{val}'");
-                            info!("{}", truncate(&msg));
-
-                            out.insert(
-                                key.clone(),
-                                Value::String(String::from(val)),
-                            );
-                        }
-                        if let Some(JsonValue::Number(val)) =
-                            selector.find(data).next()
-                        {
-                            if let Some(integer) = val.as_i64() {
-                                let msg =
-                                    format!("# Got integer '{key}' = '{val}'");
-                                info!("{}", truncate(&msg));
-                                out.insert(
-                                    key.clone(),
-                                    Value::Integer(integer),
-                                );
-                            }
-                        }
-                    }
-                    Value::Table(conf) => {
-                        let items_selector =
-                            make_selector(&get_string(conf, "_")?)?;
-                        let value_selectors = make_item_selectors(conf)?;
-
-                        // jsonpath returns an iterator that contains one
-                        // element, which is the JSON
-                        // array.
-
-                        if let Some(JsonValue::Array(items)) =
-                            items_selector.find(data).next()
-                        {
-                            let mut toml_items: Vec<Value> = Vec::new();
-
-                            for item_json in items {
-                                let mut toml_item = Table::new();
-                                for (name, selector) in &value_selectors {
-                                    if let Some(v) =
-                                        selector.find(item_json).next()
-                                    {
-                                        toml_item.insert(
-                                            name.clone(),
-                                            Value::try_from(v)?,
-                                        );
-                                    }
-                                }
+            let mut rules = table
+                .iter()
+                .map(|(key, value)| Ok((key, value, single_rule(value)?)))
+                .collect::<Result<Vec<_>>>()?;
 
-                                let raw_json =
-                                    Value::try_from(item_json.to_string())?;
-                                toml_item
-                                    .insert(String::from("_raw"), raw_json);
+            // Templates can refer to the other values, so they go last
+            rules.sort_by_key(|(_, _, rule)| {
+                rule.as_ref().is_some_and(SingleRule::is_format)
+            });
 
-                                toml_items.push(Value::Table(toml_item));
-                            }
+            for (key, value, rule) in rules {
+                // A status filter on the rule replaces the default one
+                let patterns = match &rule {
+                    Some(SingleRule {
+                        status: Some(patterns),
+                        ..
+                    }) => patterns.as_ref(),
+                    _ => when.status.as_ref(),
+                };
+                if !matches_status(status, patterns)? {
+                    debug!("# Skipped '{key}', status is {status}");
+                    continue;
+                }
+                if let Some(reason) = &body_mismatch {
+                    debug!("# Skipped '{key}', {reason}");
+                    continue;
+                }
 
-                            let msg = format!(
-                                "# Got '{}' with {} elements",
-                                key,
-                                toml_items.len()
+                match (rule, value) {
+                    (Some(rule), _) => {
+                        let val =
+                            match extract_value(rule.expr, response, &body)? {
+                                Some(val) => {
+                                    apply_chain(&rule.transforms, val, &out)
+                                        .with_context(|| {
+                                            format!("When transforming '{key}'")
+                                        })?
+                                }
+                                None => None,
+                            };
+                        let Some(val) = val else {
+                            warn!(
+                                "# No value found for '{key}' ({})",
+                                rule.expr
                             );
-                            info!("{}", truncate(&msg));
+                            continue;
+                        };
 
-                            out.insert(key.clone(), Value::Array(toml_items));
-                        }
+                        let msg = match &val {
+                            Value::String(s) => {
+                                format!("# Got string '{key}' = '{s}'")
+                            }
+                            other => format!(
+                                "# Got {} '{key}' = '{other}'",
+                                other.type_str()
+                            ),
+                        };
+                        info!("{}", truncate(&msg));
+
+                        out.insert(key.clone(), val);
+                    }
+                    (None, Value::Table(conf)) => {
+                        let Some(toml_items) = extract_list(conf, &body)?
+                        else {
+                            warn!("# No values found for '{key}'");
+                            continue;
+                        };
+
+                        let msg = format!(
+                            "# Got '{}' with {} elements",
+                            key,
+                            toml_items.len()
+                        );
+                        info!("{}", truncate(&msg));
+
+                        out.insert(key.clone(), Value::Array(toml_items));
                     }
                     _ => bail!("Invalid _extract rule: {}", value),
                 }
@@ -97,7 +259,185 @@ pub fn extract_variables(data: &JsonValue, scope: &Scope) -> Result<Table> {
     Ok(out)
 }
 
-fn make_item_selectors(conf: &Table) -> Result<Vec<(String, Selector)>> {
+/// Extract a list of selectable values, like
+/// `{ _ = "$.Tools", value = "$.ToolId", name = "$.Name" }`.
+fn extract_list(conf: &Table, body: &Body) -> Result<Option<Vec<Value>>> {
+    let items = get_string(conf, "_")?;
+
+    let data = match body {
+        Body::Json(data) => data,
+        Body::Xml(doc) => {
+            let fields = conf
+                .iter()
+                .filter(|(k, _)| *k != "_")
+                .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect::<Vec<_>>();
+
+            return Ok(doc
+                .select_items(&items, &fields)?
+                .map(|tables| tables.into_iter().map(Value::Table).collect()));
+        }
+        Body::Text => return Ok(None),
+    };
+
+    let items_selector = make_selector(&items)?;
+    let value_selectors = make_item_selectors(conf)?;
+
+    // A path like `$.items` selects the array itself, while `$.items[*]` or
+    // a filter selects the elements.
+    let nodes = items_selector.query(data).all();
+    let items = match nodes.as_slice() {
+        [] => return Ok(None),
+        [JsonValue::Array(items)] => items.iter().collect(),
+        _ => nodes,
+    };
+
+    let mut toml_items: Vec<Value> = Vec::new();
+
+    for item_json in items {
+        let mut toml_item = Table::new();
+        for (name, selector) in &value_selectors {
+            if let Some(v) = selector.query(item_json).first() {
+                toml_item.insert(name.clone(), json_to_toml(v));
+            }
+        }
+
+        let raw_json = Value::try_from(item_json.to_string())?;
+        toml_item.insert(String::from("_raw"), raw_json);
+
+        toml_items.push(Value::Table(toml_item));
+    }
+
+    Ok(Some(toml_items))
+}
+
+/// Extract a single value from the response, according to a rule like
+/// `header:Location`, `cookie:XSRF-TOKEN`, `status`, `regex:token=(\w+)`,
+/// or a JSONPath or XPath expression, depending on the type of the body.
+///
+/// A regex yields the first capture group, or the whole match if there
+/// are no groups, and a JSONPath the first match. A JSON `null` counts as
+/// not found, so that it's not saved as a value.
+fn extract_value(
+    rule: &str,
+    response: &HitmanResponse,
+    body: &Body,
+) -> Result<Option<Value>> {
+    if rule == "status" {
+        return Ok(Some(Value::Integer(response.status.as_u16().into())));
+    }
+
+    if let Some(name) = rule.strip_prefix("header:") {
+        return Ok(match response.headers.get(name.trim()) {
+            Some(value) => Some(Value::from(value.to_str()?)),
+            None => None,
+        });
+    }
+
+    if let Some(name) = rule.strip_prefix("cookie:") {
+        return Ok(find_cookie(&response.headers, name.trim()).map(Value::from));
+    }
+
+    if let Some(pattern) = rule.strip_prefix("regex:") {
+        let regex = Regex::new(pattern)?;
+        return Ok(regex
+            .captures(&response.body)
+            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+            .map(|m| Value::from(m.as_str())));
+    }
+
+    let data = match body {
+        Body::Json(data) => data,
+        Body::Xml(doc) => return doc.select(rule),
+        Body::Text => return Ok(None),
+    };
+
+    let selector = make_selector(rule)?;
+    Ok(match selector.query(data).first() {
+        None => None,
+        Some(JsonValue::Null) => {
+            debug!("# Got null for {rule}");
+            None
+        }
+        Some(value) => Some(json_to_toml(value)),
+    })
+}
+
+/// Convert a JSON value to the closest TOML value.
+///
+/// TOML has no null, so it is stored as the string `null`, which is
+/// substituted the same way in a JSON body.
+pub(crate) fn json_to_toml(value: &JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::String("null".into()),
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Value::String(s.clone()),
+        JsonValue::Array(arr) => {
+            Value::Array(arr.iter().map(json_to_toml).collect())
+        }
+        JsonValue::Object(obj) => Value::Table(
+            obj.iter()
+                .map(|(k, v)| (k.clone(), json_to_toml(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Value of a cookie set by the response.
+fn find_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next()?.split_once('='))
+        .find(|(n, _)| n.trim() == name)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Apply the current `_extract` rules to the latest stored response of a
+/// request, without sending it again. Values that are masked in the stored
+/// response can't be extracted.
+///
+/// The data file is not updated, that is left to the caller.
+pub fn extract_from_history(
+    target: &str,
+    resolved: &Resolved,
+) -> Result<Table> {
+    let entry = latest_response(resolved, target)?.with_context(|| {
+        format!(
+            "No stored response for {}",
+            resolved.original_path().display()
+        )
+    })?;
+
+    let scope = load_env(target, resolved, &[])?;
+
+    let extracted = extract_variables(&entry.response()?, &scope)?;
+    if let Some((key, _)) = extracted.iter().find(|(_, v)| is_masked(v)) {
+        bail!(
+            "'{key}' is masked in the stored response, set \
+            keep_secret_fields in _history to extract it"
+        );
+    }
+
+    Ok(extracted)
+}
+
+/// Whether a value, or anything in it, was masked in the history.
+fn is_masked(value: &Value) -> bool {
+    match value {
+        Value::String(s) => s.contains(MASK),
+        Value::Array(arr) => arr.iter().any(is_masked),
+        Value::Table(t) => t.values().any(is_masked),
+        _ => false,
+    }
+}
+
+fn make_item_selectors(conf: &Table) -> Result<Vec<(String, JsonPath)>> {
     conf.iter()
         .filter_map(|(k, v)| {
             if k == "_" {
@@ -111,8 +451,8 @@ fn make_item_selectors(conf: &Table) -> Result<Vec<(String, Selector)>> {
         .collect::<Result<Vec<_>>>()
 }
 
-fn make_selector(path: &str) -> Result<Selector> {
-    Selector::new(path).map_err(|err| anyhow!("Invalid jsonpath: {}", err))
+fn make_selector(path: &str) -> Result<JsonPath> {
+    JsonPath::parse(path).map_err(|err| anyhow!("Invalid jsonpath: {}", err))
 }
 
 fn get_string(table: &Table, key: &str) -> Result<String> {
@@ -124,8 +464,24 @@ fn get_string(table: &Table, key: &str) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use mktemp::Temp;
+    use serde_json::json;
+
+    use crate::{history::HISTORY_DIR, resolve::resolve_path};
+
     use super::*;
 
+    fn response(body: &str) -> HitmanResponse {
+        HitmanResponse {
+            status: reqwest::StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: body.to_string(),
+            elapsed: std::time::Duration::ZERO,
+        }
+    }
+
     #[test]
     fn extracts_variables_from_json() {
         let scope = toml::from_str::<Table>(
@@ -139,12 +495,11 @@ mod tests {
         .unwrap()
         .into();
 
-        let data = serde_json::from_str(
+        let data = response(
             r#"{
             "Data": { "Token": "kokobaba1234" }
         }"#,
-        )
-        .unwrap();
+        );
 
         let res = extract_variables(&data, &scope).unwrap();
 
@@ -157,7 +512,6 @@ mod tests {
 
     #[test]
     fn extracts_multiple_values_into_array() {
-        // workaround: Jsonpath crate doesn't support array
         let scope = toml::from_str::<Table>(
             r#"
         url = "example.com"
@@ -169,15 +523,14 @@ mod tests {
         .unwrap()
         .into();
 
-        let data = serde_json::from_str(
+        let data = response(
             r#"{
             "Tools": [
                 { "Name": "First tool", "ToolId": 123 },
                 { "Name": "Second tool", "ToolId": 345 }
             ]
         }"#,
-        )
-        .unwrap();
+        );
         let res = extract_variables(&data, &scope).unwrap();
 
         let expected: Table = toml::from_str(
@@ -198,4 +551,221 @@ mod tests {
         assert!(res.get("ToolId").is_some());
         assert_eq!(res.get("ToolId").unwrap(), expected.get("ToolId").unwrap(),);
     }
+
+    #[test]
+    fn extracts_lists_with_a_path_field() {
+        let scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        files = { _ = "$.files", value = "$.id", path = "$.path" }
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let data = response(r#"{ "files": [{ "id": 1, "path": "/a.txt" }] }"#);
+        let res = extract_variables(&data, &scope).unwrap();
+
+        let Some(Value::Array(files)) = res.get("files") else {
+            panic!("Expected a list, got {res:?}");
+        };
+        assert_eq!(files[0].get("value"), Some(&Value::Integer(1)));
+        assert_eq!(files[0].get("path"), Some(&Value::from("/a.txt")));
+    }
+
+    #[test]
+    fn extracts_typed_values_with_filters() {
+        let scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        price = "$.price"
+        fresh = "$.fresh"
+        owner = "$.owner"
+        note = "$.note"
+        active = "$.items[?@.active == true].id"
+        ids = "$..id"
+        missing = "$.missing"
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let data = response(
+            r#"{
+            "price": 1.5,
+            "fresh": true,
+            "owner": { "id": 7, "tags": ["a", "b"] },
+            "note": null,
+            "items": [
+                { "id": 1, "active": true },
+                { "id": 2, "active": false },
+                { "id": 3, "active": true }
+            ]
+        }"#,
+        );
+        let res = extract_variables(&data, &scope).unwrap();
+
+        let expected: Table = toml::from_str(
+            r#"
+            price = 1.5
+            fresh = true
+            owner = { id = 7, tags = ["a", "b"] }
+            active = 1
+            ids = 1
+        "#,
+        )
+        .unwrap();
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn extracts_headers_cookies_status_and_text() {
+        let scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        location = "header:Location"
+        xsrf = "cookie:XSRF-TOKEN"
+        status = "status"
+        token = 'regex:token=(\w+)'
+        missing = "$.token"
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let mut data = response("<p>token=abc123</p>");
+        data.status = reqwest::StatusCode::CREATED;
+        data.headers
+            .insert("location", "/apples/3".parse().unwrap());
+        data.headers
+            .append(SET_COOKIE, "session=xyz; HttpOnly".parse().unwrap());
+        data.headers
+            .append(SET_COOKIE, "XSRF-TOKEN=t0k; Path=/".parse().unwrap());
+
+        let res = extract_variables(&data, &scope).unwrap();
+
+        let expected: Table = toml::from_str(
+            r#"
+            location = "/apples/3"
+            xsrf = "t0k"
+            status = 201
+            token = "abc123"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn extracts_only_on_success_by_default() {
+        let scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        token = "$.token"
+        error = { path = "$.error", status = ["4xx", "5xx"] }
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let mut data = response(r#"{ "token": "abc", "error": "denied" }"#);
+        let res = extract_variables(&data, &scope).unwrap();
+        assert_eq!(res, toml::from_str("token = 'abc'").unwrap());
+
+        data.status = reqwest::StatusCode::UNAUTHORIZED;
+        let res = extract_variables(&data, &scope).unwrap();
+        assert_eq!(res, toml::from_str("error = 'denied'").unwrap());
+    }
+
+    #[test]
+    fn extracts_when_conditions_match() {
+        let scope: Scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        token = "$.token"
+
+        [_extract_when]
+        status = "any"
+        json = "$.token"
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let mut data = response(r#"{ "token": "abc" }"#);
+        data.status = reqwest::StatusCode::NOT_FOUND;
+        let res = extract_variables(&data, &scope).unwrap();
+        assert_eq!(res, toml::from_str("token = 'abc'").unwrap());
+
+        let data = response(r#"{ "error": "denied" }"#);
+        let res = extract_variables(&data, &scope).unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    fn transforms_values() {
+        let scope = toml::from_str::<Table>(
+            r#"
+        [_extract]
+        auth = { path = "$.token", transform = "format:{{type}} {{value}}" }
+        type = { path = "$.type", transform = ["lowercase", "regex:^\\w"] }
+        user = { path = "$.user", transform = "base64" }
+        "#,
+        )
+        .unwrap()
+        .into();
+
+        let data = response(
+            r#"{ "token": "abc", "type": "Bearer", "user": "YXBwbGU=" }"#,
+        );
+        let res = extract_variables(&data, &scope).unwrap();
+
+        let expected: Table = toml::from_str(
+            r#"
+            auth = "b abc"
+            type = "b"
+            user = "apple"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn extracts_variables_from_stored_response() {
+        let tmp = Temp::new_dir().unwrap();
+        fs::write(tmp.join("hitman.toml"), "[default]\n").unwrap();
+        fs::write(tmp.join("login.http"), "POST http://x/login HTTP/1.1\n")
+            .unwrap();
+        fs::write(tmp.join("login.http.toml"), "[_extract]\ntoken = \"$.t\"")
+            .unwrap();
+
+        let dir = tmp.join(HISTORY_DIR).join("login.http");
+        fs::create_dir_all(&dir).unwrap();
+        let entry = json!({
+            "request_path": "login.http",
+            "target": "default",
+            "timestamp": "2024-01-01T00:00:00Z",
+            "request": {
+                "method": "POST",
+                "url": "http://x/login",
+                "headers": [],
+                "body": null
+            },
+            "response": {
+                "status": 200,
+                "headers": [],
+                "body": "{\"t\": \"abc\"}",
+                "elapsed": { "secs": 0, "nanos": 0 }
+            }
+        });
+        fs::write(dir.join("1.json"), entry.to_string()).unwrap();
+
+        let resolved = resolve_path(&tmp.join("login.http")).unwrap();
+
+        let res = extract_from_history("default", &resolved).unwrap();
+        assert_eq!(res.get("token"), Some(&Value::from("abc")));
+
+        assert!(extract_from_history("prod", &resolved).is_err());
+    }
 }
@@ -0,0 +1,366 @@
+use std::{
+    fs::read_to_string,
+    future::Future,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use toml::{Table, Value};
+
+use crate::{
+    assertion::CheckResult,
+    env::load_env,
+    request::{make_request_with, Exchange},
+    requires::RequestChain,
+    resolve::{resolve_path, Resolved},
+    scope::Replacement,
+    util::truncate,
+};
+
+/// A sequence of requests, defined in a `.flow.toml` file.
+///
+/// Top level values are shared by all steps, and each step is defined in
+/// a `[[step]]` table.
+pub struct Flow {
+    pub vars: Table,
+    pub steps: Vec<FlowStep>,
+}
+
+pub struct FlowStep {
+    /// Path of the request file, relative to the root directory
+    pub request: String,
+
+    /// Values added to the scope for this step only, including special
+    /// sections such as `_extract`
+    pub vars: Table,
+
+    /// Number of times to make the request
+    pub repeat: usize,
+
+    /// Name of a list variable. The request is made once for each value.
+    pub foreach: Option<String>,
+}
+
+pub struct StepReport {
+    pub name: String,
+    pub status: String,
+    pub elapsed: Duration,
+    pub checks: Vec<CheckResult>,
+}
+
+/// How a flow ended, when none of its steps failed with an error.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FlowOutcome {
+    Completed,
+
+    /// The checks of a step failed, so the flow stopped after it
+    ChecksFailed {
+        step: usize,
+    },
+}
+
+impl Flow {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = read_to_string(path)?;
+        let mut vars = toml::from_str::<Table>(&content)
+            .with_context(|| format!("When reading {path:?}"))?;
+
+        let steps = match vars.remove("step") {
+            Some(Value::Array(steps)) => steps
+                .into_iter()
+                .map(FlowStep::parse)
+                .collect::<Result<Vec<_>>>()?,
+            Some(_) => bail!("Invalid flow: 'step' must be an array of tables"),
+            None => bail!("Invalid flow: no steps found"),
+        };
+
+        Ok(Self { vars, steps })
+    }
+}
+
+impl FlowStep {
+    fn parse(value: Value) -> Result<Self> {
+        let Value::Table(mut table) = value else {
+            bail!("Invalid flow step: {value}");
+        };
+
+        let request = match table.remove("request") {
+            Some(Value::String(s)) => s,
+            _ => bail!("Invalid flow step: 'request' is required"),
+        };
+
+        let mut vars = match table.remove("vars") {
+            Some(Value::Table(t)) => t,
+            None => Table::new(),
+            Some(_) => bail!("Invalid flow step: 'vars' must be a table"),
+        };
+
+        let repeat = match table.remove("repeat") {
+            Some(Value::Integer(n)) if n > 0 => usize::try_from(n)?,
+            None => 1,
+            Some(_) => bail!("Invalid flow step: 'repeat' must be positive"),
+        };
+
+        let foreach = match table.remove("foreach") {
+            Some(Value::String(s)) => Some(s),
+            None => None,
+            Some(_) => bail!("Invalid flow step: 'foreach' must be a string"),
+        };
+
+        for (key, value) in table {
+            if !key.starts_with('_') {
+                bail!("Invalid flow step: unknown key '{key}'");
+            }
+            vars.insert(key, value);
+        }
+
+        Ok(Self {
+            request,
+            vars,
+            repeat,
+            foreach,
+        })
+    }
+
+    /// Values to add to the scope for each time the request is made.
+    pub fn iterations(
+        &self,
+        target: &str,
+        resolved: &Resolved,
+        options: &[(String, String)],
+        shared: &Table,
+    ) -> Result<Vec<Table>> {
+        let bindings = match &self.foreach {
+            Some(key) => {
+                let mut scope = load_env(target, resolved, options)?;
+                scope.merge(self.scope(shared, Table::new()));
+
+                let values = match scope.lookup(key)? {
+                    Replacement::MultipleValuesFound { values, .. } => values,
+                    Replacement::Value(v) | Replacement::Literal(v) => {
+                        vec![Value::String(v)]
+                    }
+                    _ => bail!("No values found for '{key}'"),
+                };
+
+                values
+                    .into_iter()
+                    .map(|v| {
+                        let mut binding = Table::new();
+                        binding.insert(key.clone(), item_value(v));
+                        binding
+                    })
+                    .collect()
+            }
+            None => vec![Table::new()],
+        };
+
+        Ok(bindings
+            .into_iter()
+            .flat_map(|b| std::iter::repeat_n(b, self.repeat))
+            .collect())
+    }
+
+    /// Values added to the scope for one iteration of this step.
+    pub fn scope(&self, shared: &Table, binding: Table) -> Table {
+        let mut scope = shared.clone();
+        scope.extend(self.vars.clone());
+        scope.extend(binding);
+        scope
+    }
+}
+
+/// Lists can contain tables with `name` and `value`, or plain values.
+fn item_value(item: Value) -> Value {
+    match item {
+        Value::Table(mut t) => t.remove("value").unwrap_or(Value::Table(t)),
+        other => other,
+    }
+}
+
+/// Run all steps in a flow, sharing extracted values between them.
+pub async fn run_flow(
+    target: &str,
+    resolved: &Resolved,
+    options: &[(String, String)],
+) -> Result<()> {
+    let t = Instant::now();
+    let mut reports = Vec::new();
+
+    let res = run_flow_with(
+        target,
+        resolved,
+        options,
+        &mut reports,
+        |step_resolved, extra| async move {
+            let exchange = make_request_with(
+                target,
+                &step_resolved,
+                options,
+                &extra,
+                &mut RequestChain::default(),
+            )
+            .await?;
+            exchange.print_body()?;
+
+            Ok(exchange)
+        },
+    )
+    .await;
+
+    if let Ok(FlowOutcome::Completed) = res {
+        warn!("# Flow completed in {:.2?}", t.elapsed());
+    }
+    for line in transcript(&reports) {
+        warn!("# {}", truncate(&line));
+    }
+
+    if let FlowOutcome::ChecksFailed { step } = res? {
+        bail!("Step {step} failed: assertions not satisfied");
+    }
+
+    Ok(())
+}
+
+/// Run all steps in a flow, using `execute` to make each request, with the
+/// values that should be added to the scope.
+///
+/// A report for each completed step is added to `reports`. The flow stops
+/// after a step with failed checks, which are only in its report.
+pub async fn run_flow_with<F, Fut>(
+    target: &str,
+    resolved: &Resolved,
+    options: &[(String, String)],
+    reports: &mut Vec<StepReport>,
+    mut execute: F,
+) -> Result<FlowOutcome>
+where
+    F: FnMut(Resolved, Table) -> Fut,
+    Fut: Future<Output = Result<Exchange>>,
+{
+    let flow = Flow::load(resolved.original_path())?;
+
+    let mut shared = flow.vars.clone();
+
+    for (i, step) in flow.steps.iter().enumerate() {
+        let step_resolved =
+            resolve_path(&resolved.root_dir.join(&step.request))?;
+        let iterations =
+            step.iterations(target, &step_resolved, options, &shared)?;
+        let count = iterations.len();
+
+        for (j, binding) in iterations.into_iter().enumerate() {
+            let mut name = step.request.clone();
+            if count > 1 {
+                name.push_str(&format!(" ({}/{count})", j + 1));
+            }
+            for (k, v) in &binding {
+                name.push_str(&format!(" {k}={v}"));
+            }
+            warn!("# Step {}/{}: {}", i + 1, flow.steps.len(), truncate(&name));
+
+            let extra = step.scope(&shared, binding);
+            let exchange = execute(step_resolved.clone(), extra)
+                .await
+                .with_context(|| format!("Step {} failed: {name}", i + 1))?;
+
+            let passed = exchange.passed();
+            shared.extend(exchange.extracted);
+            reports.push(StepReport {
+                name,
+                status: exchange.response.status.to_string(),
+                elapsed: exchange.response.elapsed,
+                checks: exchange.checks,
+            });
+
+            if !passed {
+                return Ok(FlowOutcome::ChecksFailed { step: i + 1 });
+            }
+        }
+    }
+
+    Ok(FlowOutcome::Completed)
+}
+
+pub fn transcript(reports: &[StepReport]) -> Vec<String> {
+    let width = reports.iter().map(|r| r.name.len()).max().unwrap_or(0);
+
+    reports
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let failed = r.checks.iter().filter(|c| !c.passed()).count();
+            let checks = match (r.checks.len(), failed) {
+                (0, _) => String::new(),
+                (n, 0) => format!("  {n} checks passed"),
+                (n, f) => format!("  {f} of {n} checks failed"),
+            };
+            format!(
+                "{:>3}. {:width$}  {}  {:.2?}{checks}",
+                i + 1,
+                r.name,
+                r.status,
+                r.elapsed
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let step = FlowStep::parse(
+            toml::from_str::<Value>(
+                r#"
+                request = "apple/get_apple.http"
+                foreach = "apple_id"
+                repeat = 2
+                vars = { color = "red" }
+
+                [_extract]
+                name = "$.name"
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(step.request, "apple/get_apple.http");
+        assert_eq!(step.foreach.as_deref(), Some("apple_id"));
+        assert_eq!(step.repeat, 2);
+        assert_eq!(step.vars.get("color"), Some(&Value::from("red")));
+        assert!(step.vars.get("_extract").is_some());
+    }
+
+    #[test]
+    fn rejects_unknown_step_keys() {
+        let res = FlowStep::parse(
+            toml::from_str::<Value>(
+                r#"
+                request = "login.http"
+                color = "red"
+                "#,
+            )
+            .unwrap(),
+        );
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn uses_value_of_list_items() {
+        let item = toml::from_str::<Value>(
+            r#"name = "Apple"
+            value = 42"#,
+        )
+        .unwrap();
+
+        assert_eq!(item_value(item), Value::Integer(42));
+        assert_eq!(item_value(Value::from("a")), Value::from("a"));
+    }
+}
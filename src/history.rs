@@ -0,0 +1,549 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use log::warn;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+use walkdir::WalkDir;
+
+use crate::{
+    env::load_env,
+    redact::Redactor,
+    request::{
+        build_client, do_request, print_request, print_response, HitmanBody,
+        HitmanRequest, HitmanResponse,
+    },
+    resolve::{resolve_path, Resolved},
+    scope::Scope,
+};
+
+/// Directory in the project root, where responses are stored.
+pub const HISTORY_DIR: &str = ".hitman-history";
+
+const DEFAULT_MAX_ENTRIES: usize = 100;
+const DEFAULT_MAX_SIZE: u64 = 50 * 1024 * 1024;
+
+/// An executed request, and the response that was received.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Path of the request file, relative to the root directory
+    pub request_path: String,
+    pub target: String,
+    pub timestamp: DateTime<Utc>,
+    pub request: StoredRequest,
+    pub response: StoredResponse,
+
+    /// Whether secrets in the request were masked, so that it can't be
+    /// sent again
+    #[serde(default)]
+    pub redacted: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct StoredRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub elapsed: Duration,
+}
+
+impl StoredRequest {
+    fn new(req: &HitmanRequest) -> Self {
+        Self {
+            method: req.method.to_string(),
+            url: req.url.to_string(),
+            headers: header_pairs(&req.headers),
+            body: req.body.clone().map(HitmanBody::to_body),
+        }
+    }
+}
+
+impl HistoryEntry {
+    /// The request and the response are stored with secrets masked. Fields
+    /// of the response body with secret names are only kept with
+    /// `keep_secret_fields`, so that other requests can refer to them, like
+    /// `@login.$.token`.
+    pub fn new(
+        resolved: &Resolved,
+        target: &str,
+        req: &HitmanRequest,
+        response: &HitmanResponse,
+        redactor: &Redactor,
+        keep_secret_fields: bool,
+    ) -> Self {
+        let request = StoredRequest::new(&redactor.request(req));
+        let redacted = request != StoredRequest::new(req);
+        let masked = redactor.response(response);
+        let body = if keep_secret_fields {
+            redactor.text(&response.body)
+        } else {
+            masked.body
+        };
+
+        Self {
+            request_path: request_path(resolved),
+            target: target.to_string(),
+            timestamp: Utc::now(),
+            request,
+            redacted,
+            response: StoredResponse {
+                status: response.status.as_u16(),
+                headers: header_pairs(&masked.headers),
+                body,
+                elapsed: response.elapsed,
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("When reading {path:?}"))
+    }
+
+    /// The request, as it was sent.
+    pub fn request(&self) -> Result<HitmanRequest> {
+        Ok(HitmanRequest {
+            headers: header_map(&self.request.headers)?,
+            url: Url::parse(&self.request.url)?,
+            method: Method::from_str(&self.request.method)?,
+            body: self
+                .request
+                .body
+                .clone()
+                .map(|body| HitmanBody::Plain { body }),
+            default_headers: Vec::new(),
+        })
+    }
+
+    pub fn response(&self) -> Result<HitmanResponse> {
+        Ok(HitmanResponse {
+            status: StatusCode::from_u16(self.response.status)?,
+            headers: header_map(&self.response.headers)?,
+            body: self.response.body.clone(),
+            elapsed: self.response.elapsed,
+        })
+    }
+
+    /// A single line describing the entry.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}  {}  {} {} {}  {:.2?}",
+            self.timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            self.target,
+            self.request.method,
+            self.request_path,
+            self.response.status,
+            self.response.elapsed,
+        )
+    }
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect()
+}
+
+fn header_map(pairs: &[(String, String)]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers
+            .append(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
+    }
+
+    Ok(headers)
+}
+
+fn request_path(resolved: &Resolved) -> String {
+    let path = resolved.original_path();
+    path.strip_prefix(&resolved.root_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Limits for how much history is kept, from the `_history` section.
+struct Retention {
+    enabled: bool,
+    max_entries: usize,
+    max_size: u64,
+
+    /// Store fields with secret names in response bodies as they are
+    keep_secret_fields: bool,
+}
+
+impl Retention {
+    fn from_scope(scope: &Scope) -> Result<Self> {
+        let mut retention = Self {
+            enabled: true,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_size: DEFAULT_MAX_SIZE,
+            keep_secret_fields: false,
+        };
+
+        match scope.history() {
+            None => (),
+            Some(Value::Boolean(enabled)) => retention.enabled = *enabled,
+            Some(Value::Table(table)) => {
+                for (key, value) in table {
+                    match (key.as_str(), value) {
+                        ("enabled", Value::Boolean(b)) => {
+                            retention.enabled = *b;
+                        }
+                        ("max_entries", Value::Integer(n)) => {
+                            retention.max_entries = usize::try_from(*n)?;
+                        }
+                        ("max_size", value) => {
+                            retention.max_size = parse_size(value)?;
+                        }
+                        ("keep_secret_fields", Value::Boolean(b)) => {
+                            retention.keep_secret_fields = *b;
+                        }
+                        _ => bail!("Invalid _history option: {key}"),
+                    }
+                }
+            }
+            Some(_) => bail!("Invalid _history section"),
+        }
+
+        Ok(retention)
+    }
+}
+
+/// Parse a size in bytes, from a number or a string like "10MB".
+fn parse_size(value: &Value) -> Result<u64> {
+    let s = match value {
+        Value::Integer(n) => return Ok(u64::try_from(*n)?),
+        Value::String(s) => s.trim().to_uppercase(),
+        other => bail!("Invalid size: {other}"),
+    };
+
+    let (digits, unit) =
+        s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let factor = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => bail!("Invalid size: {s}"),
+    };
+
+    Ok(digits.parse::<u64>()? * factor)
+}
+
+/// Store the request and response in the history directory, and remove
+/// old entries that exceed the retention limits.
+pub fn record(
+    resolved: &Resolved,
+    target: &str,
+    req: &HitmanRequest,
+    response: &HitmanResponse,
+    scope: &Scope,
+) -> Result<Option<PathBuf>> {
+    let retention = Retention::from_scope(scope)?;
+    if !retention.enabled {
+        return Ok(None);
+    }
+
+    let redactor = Redactor::from_scope(scope)?;
+    let entry = HistoryEntry::new(
+        resolved,
+        target,
+        req,
+        response,
+        &redactor,
+        retention.keep_secret_fields,
+    );
+
+    let dir = resolved
+        .root_dir
+        .join(HISTORY_DIR)
+        .join(&entry.request_path);
+    fs::create_dir_all(&dir)?;
+
+    let file_name = entry.timestamp.format("%Y%m%dT%H%M%S%.6f.json");
+    let path = dir.join(file_name.to_string());
+    fs::write(&path, serde_json::to_string_pretty(&entry)?)?;
+
+    prune(&dir, &resolved.root_dir.join(HISTORY_DIR), &retention)?;
+
+    Ok(Some(path))
+}
+
+fn prune(
+    request_dir: &Path,
+    history_dir: &Path,
+    retention: &Retention,
+) -> Result<()> {
+    for path in entry_files(request_dir)
+        .into_iter()
+        .skip(retention.max_entries)
+    {
+        fs::remove_file(path)?;
+    }
+
+    let mut total = 0;
+    for path in entry_files(history_dir) {
+        total += fs::metadata(&path)?.len();
+        if total > retention.max_size {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// All stored entries in the directory, newest first.
+fn entry_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+
+    files.sort_by(|a, b| b.file_name().cmp(&a.file_name()));
+    files
+}
+
+/// Stored entries, newest first, either for all requests in the project, or
+/// for a single request.
+pub fn list_entries(
+    root_dir: &Path,
+    request: Option<&Resolved>,
+) -> Vec<PathBuf> {
+    let dir = root_dir.join(HISTORY_DIR);
+    match request {
+        Some(resolved) => entry_files(&dir.join(request_path(resolved))),
+        None => entry_files(&dir),
+    }
+}
+
+/// The most recent stored entry for a request and target.
+pub fn latest_entry(
+    root_dir: &Path,
+    request_path: &Path,
+    target: &str,
+) -> Result<Option<HistoryEntry>> {
+    for path in entry_files(&root_dir.join(HISTORY_DIR).join(request_path)) {
+        let entry = HistoryEntry::load(&path)?;
+        if entry.target == target {
+            return Ok(Some(entry));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The most recent stored entry for a resolved request.
+pub fn latest_response(
+    resolved: &Resolved,
+    target: &str,
+) -> Result<Option<HistoryEntry>> {
+    latest_entry(
+        &resolved.root_dir,
+        Path::new(&request_path(resolved)),
+        target,
+    )
+}
+
+/// Print a stored entry, like a request that was just made.
+pub fn print_entry(entry: &HistoryEntry) -> Result<()> {
+    warn!("# {}", entry.summary());
+
+    // The request is already redacted, unless secrets were shown
+    let redactor = Redactor::default();
+    print_request(&entry.request()?, &redactor);
+    let response = entry.response()?;
+    print_response(response.status, &response.headers, &redactor)?;
+
+    response.print_body(&redactor)
+}
+
+/// Send a stored request again, with the same target, and store the new
+/// response.
+pub async fn resend(root_dir: &Path, entry: &HistoryEntry) -> Result<()> {
+    if entry.redacted {
+        bail!(
+            "The entry has masked secrets, and can't be sent again. \
+            Use --show-secrets to store requests as they are sent"
+        );
+    }
+
+    let resolved = resolve_path(&root_dir.join(&entry.request_path))?;
+    let scope = load_env(&entry.target, &resolved, &[])?;
+    let client = build_client(root_dir)?;
+    let redactor = Redactor::from_scope(&scope)?;
+
+    let req = entry.request()?;
+    print_request(&req, &redactor);
+
+    let (res, elapsed) = do_request(&client, &req).await?;
+    print_response(res.status(), res.headers(), &redactor)?;
+
+    let response = HitmanResponse::receive(res, elapsed).await?;
+    record(&resolved, &entry.target, &req, &response, &scope)?;
+    response.print_body(&redactor)?;
+
+    warn!("# Request completed in {:.2?}", elapsed);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mktemp::Temp;
+
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size(&Value::Integer(100)).unwrap(), 100);
+        assert_eq!(parse_size(&Value::from("10kb")).unwrap(), 10 * 1024);
+        assert_eq!(parse_size(&Value::from("2 MB")).unwrap(), 2 * 1024 * 1024);
+        assert!(parse_size(&Value::from("2 apples")).is_err());
+    }
+
+    #[test]
+    fn stores_requests_with_secrets_masked() {
+        let tmp = Temp::new_dir().unwrap();
+        fs::write(tmp.join("hitman.toml"), "[default]").unwrap();
+        fs::write(tmp.join("login.http"), "GET /").unwrap();
+        let resolved = resolve_path(&tmp.join("login.http")).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer abc".parse().unwrap());
+        let req = HitmanRequest {
+            headers,
+            url: Url::parse("http://localhost/login").unwrap(),
+            method: Method::GET,
+            body: None,
+            default_headers: Vec::new(),
+        };
+        let response = HitmanResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: r#"{"token": "abc"}"#.to_string(),
+            elapsed: Duration::ZERO,
+        };
+
+        let scope = Scope::from(toml::Table::new());
+        let path = record(&resolved, "default", &req, &response, &scope)
+            .unwrap()
+            .unwrap();
+        let entry = HistoryEntry::load(&path).unwrap();
+
+        assert!(entry.redacted);
+        assert_eq!(
+            entry.request.headers,
+            vec![("authorization".to_string(), "********".to_string())]
+        );
+        assert_eq!(entry.response.body, r#"{"token": "********"}"#);
+    }
+
+    #[test]
+    fn stores_responses_with_secrets_masked() {
+        let tmp = Temp::new_dir().unwrap();
+        fs::write(tmp.join("hitman.toml"), "[default]").unwrap();
+        fs::write(tmp.join("echo.http"), "GET /").unwrap();
+        let resolved = resolve_path(&tmp.join("echo.http")).unwrap();
+
+        let req = HitmanRequest {
+            headers: HeaderMap::new(),
+            url: Url::parse("http://localhost/echo").unwrap(),
+            method: Method::GET,
+            body: None,
+            default_headers: Vec::new(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("set-cookie", "session=abc".parse().unwrap());
+        let response = HitmanResponse {
+            status: StatusCode::OK,
+            headers,
+            body: r#"{"token": "abc", "key": "key-123"}"#.to_string(),
+            elapsed: Duration::ZERO,
+        };
+
+        let scope = Scope::from(
+            toml::from_str::<toml::Table>("api_key = 'key-123'").unwrap(),
+        );
+        let path = record(&resolved, "default", &req, &response, &scope)
+            .unwrap()
+            .unwrap();
+        let entry = HistoryEntry::load(&path).unwrap();
+
+        assert!(!entry.redacted);
+        assert_eq!(
+            entry.response.headers,
+            vec![("set-cookie".to_string(), "********".to_string())]
+        );
+        assert_eq!(
+            entry.response.body,
+            r#"{"token": "********", "key": "********"}"#
+        );
+
+        let scope = Scope::from(
+            toml::from_str::<toml::Table>(
+                "api_key = 'key-123'\n_history = { keep_secret_fields = true }",
+            )
+            .unwrap(),
+        );
+        let path = record(&resolved, "default", &req, &response, &scope)
+            .unwrap()
+            .unwrap();
+        let entry = HistoryEntry::load(&path).unwrap();
+
+        assert_eq!(
+            entry.response.body,
+            r#"{"token": "abc", "key": "********"}"#
+        );
+    }
+
+    #[test]
+    fn prunes_old_entries() {
+        let tmp = Temp::new_dir().unwrap();
+        let dir = tmp.join("get_apples.http");
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["1.json", "2.json", "3.json"] {
+            fs::write(dir.join(name), "12345").unwrap();
+        }
+
+        let retention = Retention {
+            enabled: true,
+            max_entries: 2,
+            max_size: 5,
+            keep_secret_fields: false,
+        };
+        prune(&dir, &tmp, &retention).unwrap();
+
+        assert_eq!(entry_files(&tmp), vec![dir.join("3.json")]);
+    }
+}
@@ -1,12 +1,26 @@
+pub mod assertion;
+pub mod compare;
+pub mod dynamic;
 pub mod env;
+pub mod external;
 pub mod extract;
+pub mod flow;
 pub mod flurry;
+pub mod history;
 pub mod monitor;
+pub mod redact;
+pub mod reference;
 pub mod request;
+pub mod requires;
 pub mod resolve;
 pub mod scope;
+pub mod secrets;
+pub mod snapshot;
 pub mod substitute;
+pub mod test_runner;
+pub mod transform;
 pub mod util;
+pub mod xml;
 
 pub mod prompt;
 
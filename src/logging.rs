@@ -1,5 +1,5 @@
 use log::{
-    set_boxed_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record,
+    set_boxed_logger, set_max_level, Level, Log, Metadata, Record,
     SetLoggerError,
 };
 use std::{
@@ -17,7 +17,13 @@ struct Logger {
 /// while level doesn't affect the color.
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        // Other crates only log warnings and errors, also when verbose
+        let level = if metadata.target().starts_with("hitman") {
+            self.level
+        } else {
+            self.level.min(Level::Warn)
+        };
+        metadata.level() <= level
     }
 
     fn log(&self, record: &Record) {
@@ -53,13 +59,14 @@ pub fn init(
     quiet: bool,
     is_flurry: bool,
 ) -> Result<(), SetLoggerError> {
+    let level = match (verbose, quiet, is_flurry) {
+        (_, true, _) => Level::Error,
+        (_, _, true) => Level::Warn,
+        (true, _, _) => Level::Debug,
+        _ => Level::Info,
+    };
     let logger = Logger {
-        level: match (verbose, quiet, is_flurry) {
-            (_, true, _) => Level::Error,
-            (_, _, true) => Level::Warn,
-            (true, _, _) => Level::Debug,
-            _ => Level::Info,
-        },
+        level,
         color: if io::stderr().is_terminal() {
             ColorChoice::Auto
         } else {
@@ -68,7 +75,7 @@ pub fn init(
     };
 
     set_boxed_logger(Box::new(logger))?;
-    set_max_level(LevelFilter::Info);
+    set_max_level(level.to_level_filter());
 
     Ok(())
 }
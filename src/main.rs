@@ -1,20 +1,33 @@
-use anyhow::{Context, Result};
-use hitman::resolve::{find_root_dir, resolve_path, Resolved};
+use anyhow::{bail, Context, Result};
+use hitman::resolve::{find_root_dir, resolve_path, Resolved, ResolvedAs};
 use inquire::{list_option::ListOption, Select};
-use log::{error, info};
+use log::{error, info, warn};
 use notify::EventKind;
-use std::env::current_dir;
+use std::{env::current_dir, path::Path};
 use tokio::sync::mpsc;
 
+use hitman::compare::compare_targets;
 use hitman::env::{
     find_available_requests, get_target, load_env,
-    select_target, set_target, watch_list,
+    select_target, set_target, update_data, watch_list,
 };
+use hitman::extract::extract_from_history;
+use hitman::flow::run_flow;
 use hitman::flurry::flurry_attack;
+use hitman::history::{
+    list_entries, print_entry, resend as resend_entry, HistoryEntry,
+};
 use hitman::monitor::monitor;
-use hitman::prompt::{fuzzy_match, set_interactive_mode};
+use hitman::prompt::{fuzzy_match, get_interaction, set_interactive_mode};
+use hitman::redact::set_show_secrets;
 use hitman::request::make_request;
+use hitman::secrets::{edit_secrets, set_secret};
+use hitman::snapshot::{set_accept_mode, set_snapshot_mode};
+use hitman::test_runner::{
+    print_summary, run_tests, write_junit, write_tap, TestCase,
+};
 
+use cli::{Command, SecretsCommand};
 use watcher::Watcher;
 
 mod cli;
@@ -28,10 +41,19 @@ async fn main() -> Result<()> {
     logging::init(
         args.verbose,
         args.quiet,
-        args.flurry.is_some() || args.monitor.is_some(),
+        args.flurry.is_some()
+            || args.monitor.is_some()
+            || matches!(args.command, Some(Command::Test { .. })),
     )?;
 
     set_interactive_mode(!(args.non_interactive || args.watch));
+    set_snapshot_mode(args.snapshot);
+    set_accept_mode(args.accept);
+    set_show_secrets(args.show_secrets);
+
+    if let Some(command) = args.command {
+        return run_command(command, args.target.as_deref()).await;
+    }
 
     if let Some(arg) = args.select {
         let root_dir = find_root_dir(&current_dir()?)?.context("No hitman.toml found")?;
@@ -119,6 +141,138 @@ async fn main() -> Result<()> {
     })
 }
 
+async fn run_command(command: Command, target: Option<&str>) -> Result<()> {
+    let cwd = current_dir()?;
+
+    match command {
+        Command::Test {
+            paths,
+            junit,
+            tap,
+            accept,
+        } => {
+            set_interactive_mode(false);
+            set_accept_mode(accept);
+
+            let paths = if paths.is_empty() {
+                vec![cwd]
+            } else {
+                paths.iter().map(|p| cwd.join(p)).collect()
+            };
+
+            let cases = run_tests(target, &paths, &[]).await?;
+            print_summary(&cases);
+
+            if let Some(path) = junit {
+                write_junit(Path::new(&path), &cases)?;
+            }
+            if let Some(path) = tap {
+                write_tap(Path::new(&path), &cases)?;
+            }
+
+            let failures: usize = cases.iter().map(TestCase::failures).sum();
+            if failures > 0 {
+                bail!("Tests failed with {failures} failures");
+            }
+
+            Ok(())
+        }
+        Command::History { name, show, resend } => {
+            let root_dir =
+                find_root_dir(&cwd)?.context("No hitman.toml found")?;
+            let resolved =
+                name.map(|n| resolve_path(&cwd.join(n))).transpose()?;
+            let entries = list_entries(&root_dir, resolved.as_ref());
+
+            let selected = show.or(resend);
+            if let Some(n) = selected {
+                let path = n
+                    .checked_sub(1)
+                    .and_then(|i| entries.get(i))
+                    .with_context(|| format!("No history entry {n}"))?;
+                let entry = HistoryEntry::load(path)?;
+
+                if resend.is_some() {
+                    resend_entry(&root_dir, &entry).await?;
+                } else {
+                    print_entry(&entry)?;
+                }
+                return Ok(());
+            }
+
+            for (i, path) in entries.iter().enumerate() {
+                let entry = HistoryEntry::load(path)?;
+                println!("{:>4}  {}", i + 1, entry.summary());
+            }
+
+            Ok(())
+        }
+        Command::Extract { name, dry_run } => {
+            let resolved = resolve_path(&cwd.join(name))?;
+            let target = target.map_or_else(
+                || get_target(&resolved.root_dir),
+                ToString::to_string,
+            );
+
+            let extracted = extract_from_history(&target, &resolved)?;
+            print!("{}", toml::to_string(&extracted)?);
+
+            if dry_run {
+                warn!("# Dry run, the data file was not updated");
+            } else {
+                update_data(&resolved.root_dir, &extracted)?;
+                warn!("# Updated {} values in the data file", extracted.len());
+            }
+
+            Ok(())
+        }
+        Command::Diff {
+            name,
+            left,
+            right,
+            options,
+            ignore,
+            side_by_side,
+        } => {
+            let resolved = resolve_path(&cwd.join(name))?;
+            let comparison = compare_targets(
+                &resolved,
+                [&left, &right],
+                &options,
+                &ignore,
+                get_interaction().as_ref(),
+            )
+            .await?;
+
+            if comparison.is_identical() {
+                warn!("# Responses are identical");
+                return Ok(());
+            }
+
+            if side_by_side {
+                for line in comparison.side_by_side() {
+                    println!("{line}");
+                }
+            } else {
+                print!("{}", comparison.unified());
+            }
+
+            bail!("Responses differ");
+        }
+        Command::Secrets { command } => {
+            let root_dir =
+                find_root_dir(&cwd)?.context("No hitman.toml found")?;
+
+            match command {
+                SecretsCommand::Edit => edit_secrets(&root_dir),
+                SecretsCommand::Set { key, value } => {
+                    set_secret(&root_dir, target, &key, &value)
+                }
+            }
+        }
+    }
+}
+
 fn is_user_cancelation(err: &anyhow::Error) -> bool {
     use inquire::InquireError::*;
     matches!(
@@ -132,9 +286,11 @@ async fn run_once(
     resolved: &Resolved,
     options: &[(String, String)],
 ) -> Result<()> {
-    let scope = load_env(target, resolved, options)?;
+    if let ResolvedAs::Flow { .. } = resolved.resolved_as {
+        return run_flow(target, resolved, options).await;
+    }
 
-    make_request(resolved, &scope).await
+    make_request(target, resolved, options).await
 }
 
 async fn watch_mode(
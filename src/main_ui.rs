@@ -4,6 +4,7 @@ use std::{
 };
 
 use anyhow::Result;
+use clap::Parser;
 use crossterm::{
     terminal::{
         disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
@@ -11,14 +12,27 @@ use crossterm::{
     },
     ExecutableCommand,
 };
+use hitman::redact::set_show_secrets;
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 use ui::app::{App, Screen};
 
 mod ui;
 
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Show secrets in the output, and store them in the history, instead
+    /// of masking them
+    #[arg(long)]
+    show_secrets: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
+    set_show_secrets(args.show_secrets);
+
     init_panic_hook();
     let mut app = App::new()?;
 
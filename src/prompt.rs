@@ -1,25 +1,29 @@
 use anyhow::{bail, Result};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use inquire::{list_option::ListOption, DateSelect, MultiSelect, Select, Text};
+use log::debug;
 use std::{collections::HashMap, env, string::ToString};
 use toml::Value;
 
 use crate::{
+    dynamic::{generate, is_dynamic},
     request::HitmanRequest,
+    requires::RequirementMissing,
     resolve::Resolved,
     scope::{Replacement, Scope},
     substitute::{
-        prepare_request,
+        add_default_headers, default_headers, literal, prepare_request,
+        NestingError, Substitution,
         Substitution::{Complete, ValueMissing},
         SubstitutionValue,
     },
 };
 
-fn set_boolean(name: &str, value: bool) {
+pub(crate) fn set_boolean(name: &str, value: bool) {
     env::set_var(name, if value { "y" } else { "n" });
 }
 
-fn get_boolean(name: &str) -> bool {
+pub(crate) fn get_boolean(name: &str) -> bool {
     env::var(name).is_ok_and(|v| v == "y")
 }
 
@@ -63,20 +67,45 @@ pub fn prepare_request_interactive<I>(
 where
     I: UserInteraction + ?Sized,
 {
-    let mut vars = HashMap::new();
+    prepare_request_with_vars(resolved, scope, interaction, &mut HashMap::new())
+}
 
+/// Prepare a request, collecting substitution values in `vars`.
+///
+/// Fails with `RequirementMissing` if a value must be provided by running
+/// another request first. In that case, the values collected so far are
+/// kept in `vars`, so that the caller can try again without asking the user
+/// the same questions again.
+pub fn prepare_request_with_vars<I>(
+    resolved: &Resolved,
+    scope: &Scope,
+    interaction: &I,
+    vars: &mut HashMap<String, SubstitutionValue<String>>,
+) -> Result<HitmanRequest>
+where
+    I: UserInteraction + ?Sized,
+{
     loop {
-        match prepare_request(resolved, &vars)? {
+        match prepare_request_in_scope(resolved, scope, vars)? {
             Complete(req) => return Ok(req),
             ValueMissing {
                 key,
                 fallback,
                 multiple,
             } => {
-                let value = match scope.lookup(&key)? {
+                let (looked_up, replacement) = lookup(scope, vars, &key)?;
+
+                // The fallback is for the field, not for selecting the item
+                let fallback = fallback.filter(|_| looked_up == key);
+                let key = looked_up;
+
+                let value = match replacement {
                     Replacement::Value(value) => {
                         SubstitutionValue::Single(value)
                     }
+                    Replacement::Literal(value) => {
+                        SubstitutionValue::Single(literal(&value))
+                    }
                     Replacement::ValueNotFound { key } => {
                         SubstitutionValue::Single(
                             interaction.prompt(&key, fallback.as_deref())?,
@@ -93,6 +122,9 @@ where
                             )
                         }
                     }
+                    Replacement::RequiresRequest { key, path } => {
+                        return Err(RequirementMissing { key, path }.into());
+                    }
                 };
 
                 vars.insert(key, value);
@@ -101,6 +133,63 @@ where
     }
 }
 
+/// Prepare a request with the values collected so far, limiting nested
+/// substitution to the depth configured in the scope.
+pub fn prepare_request_in_scope(
+    resolved: &Resolved,
+    scope: &Scope,
+    vars: &HashMap<String, SubstitutionValue<String>>,
+) -> Result<Substitution<HitmanRequest>> {
+    let max_depth = scope.max_depth()?;
+    let prepared = match prepare_request(resolved, vars, max_depth) {
+        Ok(Complete(req)) => {
+            add_default_headers(req, &default_headers(scope)?, vars, max_depth)
+        }
+        other => other,
+    };
+
+    prepared.map_err(|err| match err.downcast::<NestingError>() {
+        Ok(nesting) => nesting.with_sources(scope).into(),
+        Err(err) => err,
+    })
+}
+
+/// Look up a value for a missing key. Dynamic values, like `$uuid`, are
+/// generated. For a field of a list item, like `apple_id.name`, the item is
+/// selected first, and the field is looked up from the selected item in the
+/// next round.
+///
+/// Returns the key that the replacement is for.
+pub fn lookup(
+    scope: &Scope,
+    vars: &HashMap<String, SubstitutionValue<String>>,
+    key: &str,
+) -> Result<(String, Replacement)> {
+    if is_dynamic(key) {
+        return Ok((key.to_string(), Replacement::Value(generate(key)?)));
+    }
+
+    let Some(list_key) = scope.item_list(key) else {
+        let replacement = scope.lookup(key)?;
+        if let (Replacement::Value(_) | Replacement::Literal(_), Some(source)) =
+            (&replacement, scope.source(key))
+        {
+            debug!("# Using '{key}' from {source}");
+        }
+        return Ok((key.to_string(), replacement));
+    };
+
+    match vars.get(list_key) {
+        None => Ok((list_key.to_string(), scope.lookup(list_key)?)),
+        Some(SubstitutionValue::Single(selected)) => {
+            Ok((key.to_string(), scope.lookup_field(key, selected)?))
+        }
+        Some(SubstitutionValue::Multiple(_)) => {
+            bail!("Can't use {key}, when several values are selected")
+        }
+    }
+}
+
 pub struct NoUserInteraction;
 
 impl NoUserInteraction {
@@ -0,0 +1,528 @@
+use std::env;
+
+use anyhow::{bail, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Url,
+};
+use serde_json::Value as JsonValue;
+use toml::Value;
+
+use crate::{
+    external::read_value,
+    prompt::{get_boolean, set_boolean},
+    request::{HitmanBody, HitmanRequest, HitmanResponse},
+    scope::Scope,
+    secrets::SECRETS_FILE,
+};
+
+/// Replaces secret values in printed output.
+pub const MASK: &str = "********";
+
+/// Shorter values are not masked wherever they appear, since they would
+/// match all kinds of unrelated text. Fields and headers with secret names
+/// are still masked.
+const MIN_VALUE_LEN: usize = 4;
+
+/// Names of values, headers, query parameters and JSON fields that are
+/// secret, unless configured otherwise. `*` matches any text.
+const DEFAULT_PATTERNS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "*token*",
+    "*password*",
+    "*secret*",
+    "*api_key*",
+    "*api-key*",
+    "*apikey*",
+];
+
+/// Show secret values, instead of masking them.
+pub fn set_show_secrets(enable: bool) {
+    set_boolean("show_secrets", enable);
+}
+
+pub fn is_show_secrets() -> bool {
+    get_boolean("show_secrets")
+}
+
+/// Masks secrets in requests and responses, before they are printed or
+/// stored. The real values are still sent.
+#[derive(Debug)]
+pub struct Redactor {
+    enabled: bool,
+    patterns: Vec<String>,
+    values: Vec<String>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self {
+            enabled: !is_show_secrets(),
+            patterns: DEFAULT_PATTERNS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl Redactor {
+    /// Extra patterns can be added in a `_redact` list. Values with secret
+    /// names, also in nested tables and in environment variables, and all
+    /// values from the secrets file, are masked wherever they appear.
+    pub fn from_scope(scope: &Scope) -> Result<Self> {
+        let mut redactor = Self::default();
+
+        match scope.redact() {
+            None => (),
+            Some(Value::String(pattern)) => {
+                redactor.patterns.push(pattern.to_lowercase());
+            }
+            Some(Value::Array(arr)) => {
+                for pattern in arr {
+                    let Value::String(pattern) = pattern else {
+                        bail!("Invalid _redact pattern: {pattern}");
+                    };
+                    redactor.patterns.push(pattern.to_lowercase());
+                }
+            }
+            Some(_) => bail!("Invalid _redact option"),
+        }
+
+        for (key, value) in scope.values() {
+            if key.starts_with('_') {
+                continue;
+            }
+            let secret = scope.source(key) == Some(SECRETS_FILE);
+            redactor.collect_values(key, value, secret);
+        }
+        // Environment variables with secret names, which can be used in
+        // templates like `{{$env.API_TOKEN}}`
+        for (name, value) in env::vars_os() {
+            if let (Some(name), Some(value)) = (name.to_str(), value.to_str()) {
+                if redactor.is_secret(name) {
+                    redactor.add_value(value);
+                }
+            }
+        }
+        // Replace longer values first, in case one contains another
+        redactor.values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+        redactor.values.dedup();
+
+        Ok(redactor)
+    }
+
+    /// Collect the values to mask, of a value and everything in it. Values
+    /// like `{ _env = "TOKEN" }` are included once they have been read.
+    fn collect_values(&mut self, key: &str, value: &Value, secret: bool) {
+        let secret = secret || self.is_secret(key);
+        match value {
+            Value::String(s) if secret => self.add_value(s),
+            Value::Table(table) => match read_value(table) {
+                Some(s) if secret => self.add_value(&s),
+                Some(_) => (),
+                None => {
+                    for (key, value) in table {
+                        self.collect_values(key, value, secret);
+                    }
+                }
+            },
+            Value::Array(arr) => {
+                for value in arr {
+                    self.collect_values(key, value, secret);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn add_value(&mut self, value: &str) {
+        if value.len() >= MIN_VALUE_LEN {
+            self.values.push(value.to_string());
+        }
+    }
+
+    /// Whether a name, like a header or a JSON field, is for a secret.
+    pub fn is_secret(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.patterns.iter().any(|p| glob_match(p, &name))
+    }
+
+    /// Mask secret values in a text.
+    pub fn text(&self, s: &str) -> String {
+        if !self.enabled {
+            return s.to_string();
+        }
+
+        let mut out = s.to_string();
+        for value in &self.values {
+            out = out.replace(value, MASK);
+        }
+        out
+    }
+
+    pub fn headers(&self, headers: &HeaderMap) -> HeaderMap {
+        if !self.enabled {
+            return headers.clone();
+        }
+
+        let mut out = HeaderMap::new();
+        for (name, value) in headers {
+            let value = if self.is_secret(name.as_str()) {
+                HeaderValue::from_static(MASK)
+            } else {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| HeaderValue::from_str(&self.text(v)).ok())
+                    .unwrap_or_else(|| value.clone())
+            };
+            out.append(name, value);
+        }
+        out
+    }
+
+    pub fn request(&self, req: &HitmanRequest) -> HitmanRequest {
+        if !self.enabled {
+            return req.clone();
+        }
+
+        let body = req.body.as_ref().map(|body| match body {
+            HitmanBody::Plain { body } => HitmanBody::Plain {
+                body: self.body(body),
+            },
+            HitmanBody::GraphQL { body, variables } => HitmanBody::GraphQL {
+                body: self.text(body),
+                variables: variables.as_ref().map(|v| self.json(v)),
+            },
+        });
+
+        HitmanRequest {
+            headers: self.headers(&req.headers),
+            url: self.url(&req.url),
+            method: req.method.clone(),
+            body,
+            default_headers: req.default_headers.clone(),
+        }
+    }
+
+    pub fn response(&self, res: &HitmanResponse) -> HitmanResponse {
+        if !self.enabled {
+            return res.clone();
+        }
+
+        HitmanResponse {
+            status: res.status,
+            headers: self.headers(&res.headers),
+            body: self.body(&res.body),
+            elapsed: res.elapsed,
+        }
+    }
+
+    fn url(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        if url.query_pairs().any(|(name, _)| self.is_secret(&name)) {
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .map(|(name, value)| {
+                    let value = if self.is_secret(&name) {
+                        MASK.to_string()
+                    } else {
+                        value.to_string()
+                    };
+                    (name.to_string(), value)
+                })
+                .collect();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        Url::parse(&self.text(url.as_str())).unwrap_or(url)
+    }
+
+    /// Mask secret fields in a JSON or form encoded body, and secret values
+    /// anywhere in it.
+    fn body(&self, body: &str) -> String {
+        if serde_json::from_str::<JsonValue>(body).is_ok() {
+            return self.text(&self.json_text(body));
+        } else if is_form(body) {
+            return body
+                .trim()
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, _)) if self.is_secret(name) => {
+                        format!("{name}={MASK}")
+                    }
+                    _ => self.text(pair),
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+        }
+
+        self.text(body)
+    }
+
+    /// Mask the values of secret fields in a JSON text, keeping the rest as
+    /// it is, like the order of the fields and the whitespace.
+    fn json_text(&self, json: &str) -> String {
+        let mut out = String::with_capacity(json.len());
+        let mut rest = json;
+        while let Some(start) = rest.find('"') {
+            let end = start + string_len(&rest[start..]);
+            let string = &rest[start..end];
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+
+            // A field name is followed by a colon, and then the value
+            let Some(after_colon) = rest.trim_start().strip_prefix(':') else {
+                continue;
+            };
+            let name: String = serde_json::from_str(string).unwrap_or_default();
+            if !self.is_secret(&name) {
+                continue;
+            }
+
+            let value_start = rest.len() - after_colon.trim_start().len();
+            let value_end = value_start + value_len(&rest[value_start..]);
+            out.push_str(&rest[..value_start]);
+            match &rest[value_start..value_end] {
+                "null" => out.push_str("null"),
+                _ => out.push_str(&format!("\"{MASK}\"")),
+            }
+            rest = &rest[value_end..];
+        }
+        out.push_str(rest);
+
+        out
+    }
+
+    fn json(&self, value: &JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Object(obj) => obj
+                .iter()
+                .map(|(key, value)| {
+                    let value = if self.is_secret(key) && !value.is_null() {
+                        JsonValue::from(MASK)
+                    } else {
+                        self.json(value)
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+            JsonValue::Array(arr) => arr.iter().map(|v| self.json(v)).collect(),
+            JsonValue::String(s) => JsonValue::from(self.text(s)),
+            other => other.clone(),
+        }
+    }
+}
+
+/// The length of the JSON string at the start of a text, including quotes.
+fn string_len(s: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+/// The length of the JSON value at the start of a text.
+fn value_len(s: &str) -> usize {
+    if s.starts_with('"') {
+        return string_len(s);
+    }
+    if !s.starts_with(['{', '[']) {
+        return s
+            .find(|c: char| matches!(c, ',' | '}' | ']') || c.is_whitespace())
+            .unwrap_or(s.len());
+    }
+
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'"' => {
+                i += string_len(&s[i..]);
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    s.len()
+}
+
+fn is_form(body: &str) -> bool {
+    let body = body.trim();
+    body.contains('=') && !body.contains(char::is_whitespace)
+}
+
+/// Match a lowercase name against a pattern, where `*` matches any text.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+
+    fn redactor() -> Redactor {
+        let scope = Scope::from(
+            toml::from_str::<toml::Table>(
+                r#"
+                _redact = ["session*"]
+                api_token = "tok123"
+                user = "alice"
+                session_id = "s-42"
+                "#,
+            )
+            .unwrap(),
+        );
+        Redactor::from_scope(&scope).unwrap()
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(glob_match("*token*", "access_token"));
+        assert!(glob_match("*token*", "token"));
+        assert!(glob_match("session*", "session_id"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxcyyb"));
+        assert!(!glob_match("cookie", "cookies"));
+    }
+
+    #[test]
+    fn masks_secrets_in_requests() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer abc".parse().unwrap());
+        headers.insert("x-session", "s-42".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+
+        let req = HitmanRequest {
+            headers,
+            url: Url::parse("http://x/a?user=alice&access_token=abc&t=tok123")
+                .unwrap(),
+            method: Method::POST,
+            body: Some(HitmanBody::Plain {
+                body: r#"{"user": "alice", "password": "pw", "id": "tok123"}"#
+                    .to_string(),
+            }),
+            default_headers: Vec::new(),
+        };
+
+        let redacted = redactor().request(&req).to_string();
+        assert_eq!(
+            redacted,
+            "POST http://x/a?user=alice&access_token=********&t=********\n\
+            authorization: ********\n\
+            x-session: ********\n\
+            accept: */*\n\
+            \n\
+            {\"user\": \"alice\", \"password\": \"********\", \
+            \"id\": \"********\"}\n"
+        );
+    }
+
+    #[test]
+    fn masks_nested_and_external_values() {
+        std::env::set_var("HITMAN_REDACT_TEST_TOKEN", "env-token");
+        let scope = Scope::from(
+            toml::from_str::<toml::Table>(
+                r#"
+                password = "1"
+                api_token = { _env = "HITMAN_REDACT_TEST_TOKEN" }
+                user_token = { _env = "HITMAN_REDACT_TEST_MISSING" }
+                db = { host = "db.local", password = "db-pass" }
+                tokens = ["first", { id = "second" }]
+                "#,
+            )
+            .unwrap(),
+        );
+        let redactor = Redactor::from_scope(&scope).unwrap();
+        std::env::remove_var("HITMAN_REDACT_TEST_TOKEN");
+
+        assert_eq!(
+            redactor.text("1 env-token db.local db-pass first second"),
+            "1 ******** db.local ******** ******** ********"
+        );
+    }
+
+    #[test]
+    fn masks_json_bodies_as_they_are() {
+        let body = r#"{
+  "user": "alice",
+  "auth": { "token": {"value": "a\"}b", "exp": 1}, "note": "x" },
+  "password": null,
+  "list": [{ "secret": 42 }]
+}"#;
+
+        assert_eq!(
+            redactor().body(body),
+            r#"{
+  "user": "alice",
+  "auth": { "token": "********", "note": "x" },
+  "password": null,
+  "list": [{ "secret": "********" }]
+}"#
+        );
+    }
+
+    #[test]
+    fn masks_environment_variables_with_secret_names() {
+        // Only this test uses these variables
+        env::set_var("HITMAN_REDACT_TEST_SECRET", "env-secret");
+        env::set_var("HITMAN_REDACT_TEST_NAME", "env-name");
+        let redactor = Redactor::from_scope(&Scope::from(toml::Table::new()));
+        env::remove_var("HITMAN_REDACT_TEST_SECRET");
+        env::remove_var("HITMAN_REDACT_TEST_NAME");
+
+        assert_eq!(
+            redactor.unwrap().text("env-secret env-name"),
+            "******** env-name"
+        );
+    }
+
+    #[test]
+    fn masks_form_bodies() {
+        assert_eq!(
+            redactor().body("user=alice&password=pw&x=tok123"),
+            "user=alice&password=********&x=********"
+        );
+        assert_eq!(redactor().body("plain text"), "plain text");
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value as JsonValue;
+use serde_json_path::JsonPath;
+
+use crate::{
+    env::find_available_requests,
+    history::{latest_entry, HistoryEntry},
+    redact::MASK,
+    scope::Replacement,
+};
+
+/// Stored responses of a project, for a given target, that can be referred
+/// to with expressions like `@login.$.token`.
+#[derive(Clone)]
+pub struct StoredResponses {
+    pub root_dir: PathBuf,
+    pub target: String,
+}
+
+/// A single reference, like `@login.$.token`.
+#[derive(Debug, PartialEq, Eq)]
+struct Reference<'a> {
+    request: &'a str,
+    path: &'a str,
+}
+
+pub fn is_reference(key: &str) -> bool {
+    key.starts_with('@')
+}
+
+fn parse_reference(s: &str) -> Result<Reference<'_>> {
+    let s = s.trim();
+    let Some(pos) = s.strip_prefix('@').and_then(|s| s.find(".$")) else {
+        bail!("Invalid reference: {s}");
+    };
+
+    Ok(Reference {
+        request: &s[1..=pos],
+        path: &s[pos + 3..],
+    })
+}
+
+impl StoredResponses {
+    /// Resolve an expression like `@refresh.$.token || @login.$.token`.
+    ///
+    /// The value is taken from the most recent of the stored responses. If
+    /// none of the requests have been made yet, the first one is required.
+    pub fn lookup(&self, expr: &str) -> Result<Replacement> {
+        let references = expr
+            .split("||")
+            .map(parse_reference)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut latest: Option<(&Reference, HistoryEntry)> = None;
+        for reference in &references {
+            let path = self.find_request(reference.request)?;
+            let Some(entry) =
+                latest_entry(&self.root_dir, &path, &self.target)?
+            else {
+                continue;
+            };
+
+            if latest
+                .as_ref()
+                .is_none_or(|(_, e)| entry.timestamp > e.timestamp)
+            {
+                latest = Some((reference, entry));
+            }
+        }
+
+        let Some((reference, entry)) = latest else {
+            let request = references[0].request;
+            return Ok(Replacement::RequiresRequest {
+                key: expr.to_string(),
+                path: self.find_request(request)?.display().to_string(),
+            });
+        };
+
+        let json: JsonValue = serde_json::from_str(&entry.response.body)
+            .map_err(|_| {
+                anyhow!(
+                    "Latest response from {} is not JSON",
+                    reference.request
+                )
+            })?;
+
+        match select(&json, reference.path)? {
+            Some(value) if value == MASK => bail!(
+                "${} is masked in the latest response from {}, set \
+                keep_secret_fields in _history to refer to it",
+                reference.path,
+                reference.request
+            ),
+            Some(value) => Ok(Replacement::Value(value)),
+            None => bail!(
+                "No value for ${} in latest response from {}",
+                reference.path,
+                reference.request
+            ),
+        }
+    }
+
+    /// Find the request file matching a name, either the relative path
+    /// with or without extension, or just the file name.
+    fn find_request(&self, name: &str) -> Result<PathBuf> {
+        let requests = find_available_requests(&self.root_dir)?;
+
+        if let Some(path) = requests
+            .iter()
+            .find(|p| p.with_extension("") == Path::new(name))
+        {
+            return Ok(path.clone());
+        }
+
+        let mut matching = requests.iter().filter(|p| {
+            p == &Path::new(name)
+                || p.file_stem().is_some_and(|stem| stem == name)
+        });
+
+        match (matching.next(), matching.next()) {
+            (Some(path), None) => Ok(path.clone()),
+            (Some(_), Some(_)) => bail!("Ambiguous reference: @{name}"),
+            (None, _) => bail!("Request not found: @{name}"),
+        }
+    }
+}
+
+fn select(json: &JsonValue, path: &str) -> Result<Option<String>> {
+    let jsonpath = JsonPath::parse(&format!("${path}"))
+        .map_err(|err| anyhow!("Invalid jsonpath: {err}"))?;
+
+    Ok(jsonpath.query(json).first().map(|value| match value {
+        JsonValue::String(s) => s.clone(),
+        other => other.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_reference() {
+        assert_eq!(
+            parse_reference(" @auth/login.$.result.token ").unwrap(),
+            Reference {
+                request: "auth/login",
+                path: ".result.token"
+            }
+        );
+        assert!(parse_reference("@login").is_err());
+    }
+
+    #[test]
+    fn selects_value_as_string() {
+        let json = json!({ "token": "abc", "user": { "id": 42 } });
+
+        assert_eq!(select(&json, ".token").unwrap(), Some("abc".into()));
+        assert_eq!(select(&json, ".user.id").unwrap(), Some("42".into()));
+        assert_eq!(select(&json, ".missing").unwrap(), None);
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt::{Display, Write},
     path::Path,
     str::{self, FromStr},
@@ -7,26 +8,33 @@ use std::{
 };
 
 use anyhow::{bail, Result};
-use futures::StreamExt;
+use futures::{future::LocalBoxFuture, FutureExt, StreamExt};
 use graphql_parser::query::{
     Definition, OperationDefinition, VariableDefinition,
 };
 
 use log::{info, log_enabled, warn, Level};
 use reqwest::{
-    header::{HeaderMap, CONTENT_TYPE},
-    Client, Method, Response, Url,
+    cookie::CookieStore,
+    header::{HeaderMap, HeaderName, CONTENT_TYPE},
+    Client, Method, Response, StatusCode, Url,
 };
 use serde_json::{json, Value};
 use spinoff::{spinners, Color, Spinner, Streams};
+use toml::Table as TomlTable;
 
 use crate::{
-    env::{update_data, HitmanCookieJar},
+    assertion::{check_response, print_checks, CheckResult},
+    env::{load_env, update_data, HitmanCookieJar},
     extract::extract_variables,
-    prompt::{get_interaction, prepare_request_interactive},
-    resolve::Resolved,
-    scope::Scope,
+    history::record,
+    prompt::{get_interaction, prepare_request_with_vars},
+    redact::Redactor,
+    requires::{RequestChain, RequirementMissing},
+    resolve::{resolve_path, Resolved},
+    snapshot::{verify_snapshot, Snapshot},
     util::truncate,
+    xml::pretty_print,
 };
 
 #[derive(Clone)]
@@ -75,18 +83,25 @@ pub struct HitmanRequest {
     pub url: Url,
     pub method: Method,
     pub body: Option<HitmanBody>,
+
+    /// Headers that were added from `_headers`, and not by the template
+    pub default_headers: Vec<HeaderName>,
 }
 
 impl Display for HitmanRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{} {}", self.method.as_str(), self.url.as_str())?;
         for (key, val) in &self.headers {
-            writeln!(
+            write!(
                 f,
                 "{}: {}",
                 key.as_str(),
                 val.to_str().unwrap_or("unknown value")
             )?;
+            if self.default_headers.contains(key) {
+                write!(f, "  # default")?;
+            }
+            writeln!(f)?;
         }
 
         if let Some(ref body) = self.body {
@@ -103,22 +118,204 @@ static USER_AGENT: &str =
     concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 pub fn build_client(root_dir: &Path) -> Result<Client> {
+    build_client_with_cookies(Arc::new(HitmanCookieJar::new(root_dir)))
+}
+
+/// Build a client that keeps cookies in the given store.
+pub fn build_client_with_cookies<C>(cookies: Arc<C>) -> Result<Client>
+where
+    C: CookieStore + 'static,
+{
     let client = Client::builder()
         .user_agent(USER_AGENT)
-        .cookie_provider(Arc::new(HitmanCookieJar::new(root_dir)))
+        .cookie_provider(cookies)
         .build()?;
     Ok(client)
 }
 
-pub async fn make_request(resolved: &Resolved, scope: &Scope) -> Result<()> {
+/// A response that has been received in full.
+#[derive(Clone)]
+pub struct HitmanResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+    pub elapsed: Duration,
+}
+
+impl HitmanResponse {
+    pub async fn receive(
+        response: Response,
+        elapsed: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.text().await?,
+            elapsed,
+        })
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.body).ok()
+    }
+
+    pub fn is_xml(&self) -> bool {
+        self.headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("xml"))
+    }
+
+    /// The body, formatted for display, if it is JSON or XML.
+    pub fn formatted_body(&self) -> Result<Option<String>> {
+        if let Some(json) = self.json() {
+            return Ok(Some(serde_json::to_string_pretty(&json)?));
+        }
+
+        if self.is_xml() {
+            let xml = pretty_print(&self.body);
+            return Ok(Some(xml.unwrap_or_else(|_| self.body.clone())));
+        }
+
+        Ok(None)
+    }
+
+    /// Print the body to stdout, with secrets masked, if there is something
+    /// worth printing.
+    pub fn print_body(&self, redactor: &Redactor) -> Result<()> {
+        if let Some(body) = redactor.response(self).formatted_body()? {
+            println!("{body}");
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of making a request.
+pub struct Exchange {
+    pub response: HitmanResponse,
+
+    /// Variables extracted from the response
+    pub extracted: TomlTable,
+
+    /// Results of the checks in the `_assert` section, and the snapshot
+    /// comparison
+    pub checks: Vec<CheckResult>,
+
+    /// The response snapshot, if snapshots are enabled for the request
+    pub snapshot: Option<Snapshot>,
+
+    /// Masks secrets of the request's scope in the response
+    pub redactor: Redactor,
+}
+
+impl Exchange {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(CheckResult::passed)
+    }
+
+    pub fn print_body(&self) -> Result<()> {
+        self.response.print_body(&self.redactor)
+    }
+}
+
+pub async fn make_request(
+    target: &str,
+    resolved: &Resolved,
+    options: &[(String, String)],
+) -> Result<()> {
+    let exchange = make_request_with(
+        target,
+        resolved,
+        options,
+        &TomlTable::new(),
+        &mut RequestChain::default(),
+    )
+    .await?;
+
+    exchange.print_body()
+}
+
+/// Make a request, with `extra` values added to the scope.
+///
+/// Any requests declared in `_requires`, that are needed to provide missing
+/// values, are made first, without the `extra` values.
+///
+/// The response body is not printed, that is left to the caller.
+pub fn make_request_with<'a>(
+    target: &'a str,
+    resolved: &'a Resolved,
+    options: &'a [(String, String)],
+    extra: &'a TomlTable,
+    chain: &'a mut RequestChain,
+) -> LocalBoxFuture<'a, Result<Exchange>> {
+    async move {
+        chain.enter(resolved)?;
+        let result =
+            make_request_in_chain(target, resolved, options, extra, chain)
+                .await;
+        chain.leave();
+
+        result
+    }
+    .boxed_local()
+}
+
+/// Make the request, once it's been entered in the chain, so that it always
+/// leaves the chain again, also on errors.
+async fn make_request_in_chain(
+    target: &str,
+    resolved: &Resolved,
+    options: &[(String, String)],
+    extra: &TomlTable,
+    chain: &mut RequestChain,
+) -> Result<Exchange> {
     let client = build_client(&resolved.root_dir)?;
 
     let interaction = get_interaction();
 
-    let req =
-        prepare_request_interactive(resolved, scope, interaction.as_ref())?;
+    let mut vars = HashMap::new();
+    let mut provided = Vec::new();
+    let (req, scope) = loop {
+        let mut scope = load_env(target, resolved, options)?;
+        scope.merge(extra.clone());
+
+        match prepare_request_with_vars(
+            resolved,
+            &scope,
+            interaction.as_ref(),
+            &mut vars,
+        ) {
+            Ok(req) => break (req, scope),
+            Err(err) => {
+                let RequirementMissing { key, path } = err.downcast()?;
+                if provided.contains(&key) {
+                    bail!("{path} did not provide a value for '{key}'");
+                }
 
-    print_request(&req);
+                warn!(
+                    "# {}Running {path} to provide '{key}'",
+                    "  ".repeat(chain.depth() - 1)
+                );
+                // The extra values are only for this request, like the
+                // values of a flow step
+                let dependency = resolve_path(&resolved.root_dir.join(&path))?;
+                make_request_with(
+                    target,
+                    &dependency,
+                    options,
+                    &TomlTable::new(),
+                    chain,
+                )
+                .await?;
+
+                provided.push(key);
+            }
+        }
+    };
+
+    let redactor = Redactor::from_scope(&scope)?;
+    print_request(&req, &redactor);
 
     let mut spinner = Spinner::new_with_stream(
         spinners::BouncingBar,
@@ -129,24 +326,54 @@ pub async fn make_request(resolved: &Resolved, scope: &Scope) -> Result<()> {
     let (response, elapsed) = do_request(&client, &req).await?;
     spinner.stop();
 
-    print_response(&response)?;
+    print_response(response.status(), response.headers(), &redactor)?;
 
     // Subscription for graphql is a stream
     if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
         if content_type.to_str()?.contains("text/event-stream") {
-            return parse_stream_output(response).await;
+            let status = response.status();
+            let headers = response.headers().clone();
+            parse_stream_output(response).await?;
+
+            let response = HitmanResponse {
+                status,
+                headers,
+                body: String::new(),
+                elapsed,
+            };
+            return Ok(Exchange {
+                response,
+                extracted: TomlTable::new(),
+                checks: Vec::new(),
+                snapshot: None,
+                redactor,
+            });
         }
     }
 
-    if let Ok(json) = response.json::<Value>().await {
-        println!("{}", serde_json::to_string_pretty(&json)?);
-        let vars = extract_variables(&json, scope)?;
-        update_data(&resolved.root_dir, &vars)?;
+    let response = HitmanResponse::receive(response, elapsed).await?;
+    record(resolved, target, &req, &response, &scope)?;
+
+    let extracted = extract_variables(&response, &scope)?;
+    update_data(&resolved.root_dir, &extracted)?;
+
+    let mut checks = check_response(&response, &scope)?;
+    let snapshot = verify_snapshot(resolved, &response, &scope)?;
+    if let Some(snapshot) = &snapshot {
+        snapshot.print();
+        checks.extend(snapshot.check());
     }
+    print_checks(&checks);
 
     warn!("# Request completed in {:.2?}", elapsed);
 
-    Ok(())
+    Ok(Exchange {
+        response,
+        extracted,
+        checks,
+        snapshot,
+        redactor,
+    })
 }
 
 async fn parse_stream_output(response: Response) -> Result<()> {
@@ -186,17 +413,22 @@ pub async fn do_request(
     Ok((response, elapsed))
 }
 
-fn print_request(req: &HitmanRequest) {
+/// Print the request, with secrets masked.
+pub fn print_request(req: &HitmanRequest, redactor: &Redactor) {
     if log_enabled!(Level::Info) {
-        for line in req.to_string().lines() {
+        for line in redactor.request(req).to_string().lines() {
             info!("> {}", truncate(line));
         }
     }
 }
 
-fn print_response(res: &Response) -> Result<()> {
+/// Print the status and headers of a response, with secrets masked.
+pub fn print_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    redactor: &Redactor,
+) -> Result<()> {
     if log_enabled!(Level::Info) {
-        let status = res.status();
         info!(
             "< HTTP/1.1 {} {}",
             status.as_u16(),
@@ -204,7 +436,7 @@ fn print_response(res: &Response) -> Result<()> {
         );
 
         let mut head = String::new();
-        for (name, value) in res.headers() {
+        for (name, value) in &redactor.headers(headers) {
             writeln!(head, "{}: {}", name, value.to_str()?)?;
         }
 
@@ -243,6 +475,23 @@ pub struct GraphQLRequest {
 pub struct GraphQLVariable {
     pub name: String,
     pub list: bool,
+
+    /// The type is an input object, or a custom scalar, so a table is sent
+    /// as a JSON object
+    pub object: bool,
+}
+
+fn is_object_type(var_type: &graphql_parser::query::Type<String>) -> bool {
+    use graphql_parser::query::Type;
+
+    match var_type {
+        Type::NamedType(name) => !matches!(
+            name.as_str(),
+            "String" | "Int" | "Float" | "Boolean" | "ID"
+        ),
+        Type::NonNullType(inner) => is_object_type(inner),
+        Type::ListType(_) => false,
+    }
 }
 
 pub fn find_args<P>(path: P) -> Result<Vec<GraphQLVariable>>
@@ -260,6 +509,7 @@ where
                     d.var_type,
                     graphql_parser::query::Type::ListType(_)
                 ),
+                object: is_object_type(&d.var_type),
             })
             .collect::<Vec<_>>()
     };
@@ -0,0 +1,61 @@
+use std::{
+    fs::canonicalize,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+use crate::resolve::Resolved;
+
+/// Raised while preparing a request, when a value is missing, and the
+/// `_requires` section declares another request that produces it.
+#[derive(Debug, Error)]
+#[error("Value for '{key}' must be provided by {path}")]
+pub struct RequirementMissing {
+    pub key: String,
+    pub path: String,
+}
+
+/// Keeps track of the requests currently being executed, in order to
+/// detect cyclic requirements.
+#[derive(Default)]
+pub struct RequestChain(Vec<(PathBuf, String)>);
+
+impl RequestChain {
+    pub fn enter(&mut self, resolved: &Resolved) -> Result<()> {
+        let path = resolved.original_path();
+        let canonical = canonicalize(path).unwrap_or_else(|_| path.into());
+        let name = display_name(&resolved.root_dir, path);
+
+        if self.0.iter().any(|(p, _)| *p == canonical) {
+            let trace = self
+                .0
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([name.as_str()])
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            bail!("Cyclic requirement: {trace}");
+        }
+
+        self.0.push((canonical, name));
+
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.0.pop();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+}
+
+fn display_name(root_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(root_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
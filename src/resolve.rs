@@ -17,8 +17,12 @@ pub enum ResolvedAs {
         wrapper_path: Box<Path>,
         graphql_path: Box<Path>,
     },
+    Flow {
+        path: Box<Path>,
+    },
 }
 
+#[derive(Clone)]
 pub struct Resolved {
     pub root_dir: Box<Path>,
     pub resolved_as: ResolvedAs,
@@ -27,7 +31,7 @@ pub struct Resolved {
 impl Resolved {
     pub fn original_path(&self) -> &Path {
         match &self.resolved_as {
-            ResolvedAs::Simple { path } => path,
+            ResolvedAs::Simple { path } | ResolvedAs::Flow { path } => path,
             ResolvedAs::GraphQL { graphql_path, .. } => graphql_path,
         }
     }
@@ -40,9 +44,18 @@ impl Resolved {
         }
     }
 
+    /// Path of the stored response snapshot, next to the request file.
+    pub fn snapshot_path(&self) -> PathBuf {
+        let orig = self.original_path();
+        match orig.extension() {
+            Some(ext) => orig.with_extension(with_suffix(ext, ".snap")),
+            None => orig.with_extension("snap"),
+        }
+    }
+
     pub fn http_file(&self) -> &Path {
         match &self.resolved_as {
-            ResolvedAs::Simple { path } => path,
+            ResolvedAs::Simple { path } | ResolvedAs::Flow { path } => path,
             ResolvedAs::GraphQL { wrapper_path, .. } => wrapper_path,
         }
     }
@@ -51,7 +64,9 @@ impl Resolved {
 pub fn resolve_path(path: &Path) -> Result<Resolved> {
     let root_dir = find_root_dir(path)?.unwrap_or(current_dir()?.into());
 
-    let resolved_as = if is_graphql(path) {
+    let resolved_as = if is_flow(path) {
+        ResolvedAs::Flow { path: path.into() }
+    } else if is_graphql(path) {
         let template_path = resolve_graphql_http_file(path)?;
         ResolvedAs::GraphQL {
             wrapper_path: template_path.into(),
@@ -74,6 +89,12 @@ pub fn is_graphql(path: &Path) -> bool {
     }
 }
 
+pub fn is_flow(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .is_some_and(|s| s.to_lowercase().ends_with(".flow.toml"))
+}
+
 // The root dir is where we find hitman.toml,
 // scanning parent directories until we find it
 pub fn find_root_dir(path: &Path) -> Result<Option<Box<Path>>> {
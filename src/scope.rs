@@ -1,13 +1,35 @@
-use anyhow::bail;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value as JsonValue;
+use serde_json_path::JsonPath;
 use toml::{Table, Value};
 
+use crate::{
+    env::merge,
+    external::external_value,
+    extract::json_to_toml,
+    reference::{is_reference, StoredResponses},
+    substitute::DEFAULT_MAX_DEPTH,
+};
+
 #[derive(Clone)]
-pub struct Scope(Table);
+pub struct Scope {
+    values: Table,
+    responses: Option<StoredResponses>,
+
+    /// The file each value came from
+    sources: HashMap<String, String>,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Replacement {
     Value(String),
 
+    /// A value that is used as it is, without substituting placeholders in
+    /// it again, like a table as JSON or a value from the environment
+    Literal(String),
+
     ValueNotFound {
         key: String,
     },
@@ -16,40 +38,295 @@ pub enum Replacement {
         key: String,
         values: Vec<toml::Value>,
     },
+
+    /// No value found, but the request file at `path` is declared to
+    /// produce it (see `_requires`)
+    RequiresRequest {
+        key: String,
+        path: String,
+    },
 }
 
 impl From<Table> for Scope {
     fn from(env: Table) -> Self {
-        Self(env)
+        Self {
+            values: env,
+            responses: None,
+            sources: HashMap::new(),
+        }
     }
 }
 
 impl Scope {
+    /// Allow looking up values in stored responses, like `@login.$.token`.
+    #[must_use]
+    pub fn with_responses(mut self, responses: StoredResponses) -> Self {
+        self.responses = Some(responses);
+        self
+    }
+
+    /// Remember which file each value came from, for error messages.
+    #[must_use]
+    pub fn with_sources(mut self, sources: HashMap<String, String>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// The file that the value for a key came from, like `hitman.toml`.
+    pub fn source(&self, key: &str) -> Option<&str> {
+        let name = parse_path(key).map_or(key, |path| path.name);
+        self.sources.get(name).map(String::as_str)
+    }
+
     pub fn lookup(&self, key: &str) -> anyhow::Result<Replacement> {
-        let rep = match self.0.get(key) {
-            None => Replacement::ValueNotFound { key: key.into() },
-            Some(Value::String(v)) => Replacement::Value(v.clone()),
-            Some(Value::Integer(v)) => Replacement::Value(v.to_string()),
-            Some(Value::Float(v)) => Replacement::Value(v.to_string()),
-            Some(Value::Boolean(v)) => Replacement::Value(v.to_string()),
-            Some(Value::Array(arr)) => Replacement::MultipleValuesFound {
-                key: key.into(),
-                values: arr.clone(),
-            },
-            Some(_) => bail!("Type not supported"),
+        if is_reference(key) {
+            return match &self.responses {
+                Some(responses) => responses.lookup(key),
+                None => Ok(Replacement::ValueNotFound { key: key.into() }),
+            };
+        }
+
+        if let Some(value) = self.values.get(key) {
+            return to_replacement(key, value);
+        }
+
+        // A path into a table or an array, like `db.host` or `hosts[0]`
+        let Some(path) = parse_path(key) else {
+            return Ok(Replacement::ValueNotFound { key: key.into() });
         };
+        let name = path.name;
+        if !self.values.contains_key(name) {
+            return Ok(match self.requirement(name) {
+                Some(path) => Replacement::RequiresRequest {
+                    key: name.into(),
+                    path: path.into(),
+                },
+                None => Replacement::ValueNotFound { key: key.into() },
+            });
+        }
+
+        let mut value = self.values.get(name);
+        for segment in path.segments {
+            value = match (value, segment) {
+                (Some(Value::Table(t)), Segment::Field(field)) => t.get(field),
+                (Some(Value::Array(arr)), Segment::Index(i)) => arr.get(i),
+                _ => None,
+            };
+        }
+
+        match value {
+            Some(value) => to_replacement(key, value),
+            None => Ok(Replacement::ValueNotFound { key: key.into() }),
+        }
+    }
+
+    /// The key of the list, if `key` refers to a field of a list item, like
+    /// `apple_id.name`. The item must be selected before the field can be
+    /// looked up with `lookup_field`.
+    pub fn item_list<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let (list_key, _) = split_field(key)?;
+
+        matches!(self.values.get(list_key), Some(Value::Array(_)))
+            .then_some(list_key)
+    }
+
+    /// Look up a field of a selected list item, like `apple_id.name` or
+    /// `apple_id._raw.$.owner.id`, where `selected` is the value that was
+    /// selected for `apple_id`.
+    pub fn lookup_field(
+        &self,
+        key: &str,
+        selected: &str,
+    ) -> anyhow::Result<Replacement> {
+        let Some((list_key, field)) = split_field(key) else {
+            return self.lookup(key);
+        };
+
+        let item = match self.values.get(list_key) {
+            Some(Value::Array(items)) => {
+                items.iter().find(|item| item_value(item) == selected)
+            }
+            _ => None,
+        };
+
+        let value = match (item, field.split_once(".$")) {
+            (Some(item), Some(("_raw", path))) => select_raw(item, path)?,
+            (Some(item), _) => item.get(field).cloned(),
+            (None, _) => None,
+        };
+
+        match value {
+            Some(value) => to_replacement(key, &value),
+            None => Ok(Replacement::ValueNotFound { key: key.into() }),
+        }
+    }
 
-        Ok(rep)
+    /// Merge values into the scope, merging child tables into existing
+    /// child tables, such as `_extract`.
+    pub fn merge(&mut self, other: Table) {
+        merge(&mut self.values, other);
     }
 
     pub fn extract(&self) -> Option<&Value> {
-        self.0.get("_extract")
+        self.values.get("_extract")
     }
+
+    pub fn extract_when(&self) -> Option<&Value> {
+        self.values.get("_extract_when")
+    }
+
+    pub fn assertions(&self) -> Option<&Value> {
+        self.values.get("_assert")
+    }
+
+    pub fn snapshot(&self) -> Option<&Value> {
+        self.values.get("_snapshot")
+    }
+
+    pub fn comparison(&self) -> Option<&Value> {
+        self.values.get("_compare")
+    }
+
+    pub fn history(&self) -> Option<&Value> {
+        self.values.get("_history")
+    }
+
+    pub fn headers(&self) -> Option<&Value> {
+        self.values.get("_headers")
+    }
+
+    pub fn redact(&self) -> Option<&Value> {
+        self.values.get("_redact")
+    }
+
+    pub fn values(&self) -> &Table {
+        &self.values
+    }
+
+    /// How many levels values can refer to other values.
+    pub fn max_depth(&self) -> anyhow::Result<usize> {
+        match self.values.get("_max_depth") {
+            None => Ok(DEFAULT_MAX_DEPTH),
+            Some(Value::Integer(n)) => Ok(usize::try_from(*n)?),
+            Some(_) => bail!("Invalid _max_depth option"),
+        }
+    }
+
+    /// Path of the request that is declared to produce a value for `key`,
+    /// relative to the root directory.
+    pub fn requirement(&self, key: &str) -> Option<&str> {
+        self.values.get("_requires")?.get(key)?.as_str()
+    }
+}
+
+/// A key followed by fields and indexes, like `db.replicas[0].host`.
+struct Path<'a> {
+    name: &'a str,
+    segments: Vec<Segment<'a>>,
+}
+
+enum Segment<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+fn parse_path(key: &str) -> Option<Path<'_>> {
+    let end = key.find(['.', '[']).unwrap_or(key.len());
+    let (name, mut rest) = key.split_at(end);
+
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('.') {
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            segments.push(Segment::Field(&r[..end]));
+            rest = &r[end..];
+        } else {
+            let (index, r) = rest.strip_prefix('[')?.split_once(']')?;
+            segments.push(Segment::Index(index.trim().parse().ok()?));
+            rest = r;
+        }
+    }
+
+    Some(Path { name, segments })
+}
+
+/// Split a key like `apple_id.name` into the list key and the field.
+fn split_field(key: &str) -> Option<(&str, &str)> {
+    if is_reference(key) {
+        return None;
+    }
+
+    key.split_once('.')
+}
+
+/// The replacement for a value, where tables like `{ _env = "API_TOKEN" }`
+/// are resolved only when they are used.
+fn to_replacement(key: &str, value: &Value) -> anyhow::Result<Replacement> {
+    Ok(match value {
+        Value::String(v) => Replacement::Value(v.clone()),
+        Value::Integer(v) => Replacement::Value(v.to_string()),
+        Value::Float(v) => Replacement::Value(v.to_string()),
+        Value::Boolean(v) => Replacement::Value(v.to_string()),
+        Value::Datetime(v) => Replacement::Value(v.to_string()),
+        Value::Array(arr) => Replacement::MultipleValuesFound {
+            key: key.into(),
+            values: arr.clone(),
+        },
+        Value::Table(t) => match external_value(t) {
+            Some(external) => Replacement::Literal(
+                external.with_context(|| format!("When looking up {key}"))?,
+            ),
+            None => Replacement::Literal(toml_to_json(value).to_string()),
+        },
+    })
+}
+
+/// Convert a TOML value to JSON, with datetimes as strings.
+fn toml_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::String(s) => JsonValue::from(s.as_str()),
+        Value::Integer(i) => JsonValue::from(*i),
+        Value::Float(f) => JsonValue::from(*f),
+        Value::Boolean(b) => JsonValue::from(*b),
+        Value::Datetime(dt) => JsonValue::from(dt.to_string()),
+        Value::Array(arr) => arr.iter().map(toml_to_json).collect(),
+        Value::Table(t) => JsonValue::Object(
+            t.iter()
+                .map(|(k, v)| (k.clone(), toml_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// The value that is substituted when selecting a list item.
+pub fn item_value(item: &Value) -> String {
+    match item {
+        Value::Table(t) => match t.get("value") {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        },
+        other => other.to_string(),
+    }
+}
+
+/// Select a value from the JSON of a list item, stored in `_raw`.
+fn select_raw(item: &Value, path: &str) -> anyhow::Result<Option<Value>> {
+    let Some(Value::String(raw)) = item.get("_raw") else {
+        return Ok(None);
+    };
+
+    let json: JsonValue = serde_json::from_str(raw)?;
+    let jsonpath = JsonPath::parse(&format!("${path}"))
+        .map_err(|err| anyhow!("Invalid jsonpath: {err}"))?;
+
+    Ok(jsonpath.query(&json).first().map(json_to_toml))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::substitute::{literal, substitute, Complete, SubstitutionValue};
 
     fn create_env() -> Scope {
         toml::from_str::<Table>(
@@ -63,6 +340,20 @@ mod tests {
 
             nested = "the answer is {{integer}}"
             multiple = ["a", "b", "c"]
+            released = 1979-05-27T07:32:00Z
+            hosts = ["a.com", "b.com"]
+            pear_id = [
+                { value = 1, name = "Red", _raw = '{"owner":{"id":7}}' },
+                { value = 2, name = "Green", _raw = '{"owner":{"id":8}}' },
+            ]
+
+            [db]
+            host = "localhost"
+            port = 5432
+            replicas = [{ host = "r1" }]
+
+            [_requires]
+            apple_id = "apple/get_apples.http"
             "#,
         )
         .unwrap()
@@ -127,4 +418,95 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn requires_request_for_missing() {
+        let rep = create_env();
+        let res = rep.lookup("apple_id").unwrap();
+
+        assert_eq!(
+            res,
+            Replacement::RequiresRequest {
+                key: "apple_id".into(),
+                path: "apple/get_apples.http".into()
+            }
+        );
+    }
+
+    #[test]
+    fn finds_field_of_selected_item() {
+        let rep = create_env();
+
+        assert_eq!(
+            rep.lookup_field("pear_id.name", "2").unwrap(),
+            Replacement::Value("Green".into())
+        );
+        assert_eq!(
+            rep.lookup_field("pear_id._raw.$.owner.id", "1").unwrap(),
+            Replacement::Value("7".into())
+        );
+        assert_eq!(
+            rep.lookup_field("pear_id.color", "1").unwrap(),
+            Replacement::ValueNotFound {
+                key: "pear_id.color".into()
+            }
+        );
+    }
+
+    #[test]
+    fn finds_nested_values() {
+        let rep = create_env();
+
+        let values = [
+            ("db.host", "localhost"),
+            ("db.port", "5432"),
+            ("db.replicas[0].host", "r1"),
+            ("hosts[1]", "b.com"),
+            ("released", "1979-05-27T07:32:00Z"),
+        ];
+        for (key, value) in values {
+            assert_eq!(
+                rep.lookup(key).unwrap(),
+                Replacement::Value(value.into())
+            );
+        }
+
+        assert_eq!(
+            rep.lookup("db.replicas[0]").unwrap(),
+            Replacement::Literal(r#"{"host":"r1"}"#.into())
+        );
+        assert_eq!(
+            rep.lookup("hosts[2]").unwrap(),
+            Replacement::ValueNotFound {
+                key: "hosts[2]".into()
+            }
+        );
+        assert_eq!(
+            rep.lookup("apple_id.name").unwrap(),
+            Replacement::RequiresRequest {
+                key: "apple_id".into(),
+                path: "apple/get_apples.http".into()
+            }
+        );
+    }
+
+    #[test]
+    fn substitutes_nested_tables_as_they_are() {
+        let mut rep = create_env();
+        rep.merge(toml::from_str("[db.options.pool]\nsize = 2\n").unwrap());
+
+        let Replacement::Literal(json) = rep.lookup("db.options").unwrap()
+        else {
+            panic!("Expected a literal value");
+        };
+        let vars = HashMap::from([(
+            "db.options".to_string(),
+            SubstitutionValue::Single(literal(&json)),
+        )]);
+
+        assert_eq!(
+            substitute("{{ db.options }}\n", &vars).unwrap(),
+            Complete(r#"{"pool":{"size":2}}"#.to_string() + "\n")
+        );
+    }
 }
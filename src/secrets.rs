@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{Read, Write},
+    iter,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use age::{secrecy::SecretString, x25519, Decryptor, Encryptor};
+use anyhow::{bail, Context, Result};
+use inquire::Password;
+use log::{debug, warn};
+use toml::{Table, Value};
+
+use crate::prompt::is_interactive_mode;
+
+pub const SECRETS_FILE: &str = "hitman.secrets.toml.age";
+
+/// The public keys of everyone that can decrypt the secrets file, when
+/// identities are used instead of a passphrase.
+const RECIPIENTS_FILE: &str = "hitman.secrets.recipients";
+
+const PASSPHRASE_VAR: &str = "HITMAN_PASSPHRASE";
+const IDENTITY_VAR: &str = "HITMAN_AGE_IDENTITY";
+
+/// What the secrets file is encrypted with.
+#[derive(Clone)]
+pub enum Key {
+    Passphrase(SecretString),
+    Identities(Vec<x25519::Identity>),
+}
+
+impl Key {
+    /// Read the key from an identity file, if `HITMAN_AGE_IDENTITY` is set,
+    /// or use a passphrase from `HITMAN_PASSPHRASE`, or ask for it.
+    fn from_env(confirm: bool) -> Result<Self> {
+        if let Ok(path) = env::var(IDENTITY_VAR) {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("When reading {path}"))?;
+            return Self::parse_identities(&content)
+                .with_context(|| format!("When reading {path}"));
+        }
+
+        if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+            return Ok(Self::Passphrase(passphrase.into()));
+        }
+
+        if !is_interactive_mode() {
+            bail!(
+                "{SECRETS_FILE} is encrypted, \
+                set {PASSPHRASE_VAR} or {IDENTITY_VAR}"
+            );
+        }
+
+        let message = format!("Passphrase for {SECRETS_FILE}");
+        let prompt = Password::new(&message);
+        let prompt = if confirm {
+            prompt
+        } else {
+            prompt.without_confirmation()
+        };
+
+        Ok(Self::Passphrase(prompt.prompt()?.into()))
+    }
+
+    /// Parse the `AGE-SECRET-KEY-...` lines of an identity file.
+    pub fn parse_identities(content: &str) -> Result<Self> {
+        let identities = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.parse::<x25519::Identity>()
+                    .map_err(|e| anyhow::anyhow!("Invalid identity: {e}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if identities.is_empty() {
+            bail!("No identities found");
+        }
+
+        Ok(Self::Identities(identities))
+    }
+
+    /// Encrypt for the recipients, as well as for the own identities, so
+    /// that everyone can still decrypt the file after it's changed.
+    pub fn encrypt(
+        &self,
+        plaintext: &str,
+        recipients: &[x25519::Recipient],
+    ) -> Result<Vec<u8>> {
+        let encryptor = match self {
+            Self::Passphrase(_) if !recipients.is_empty() => {
+                bail!(
+                    "{SECRETS_FILE} is encrypted for the keys in \
+                    {RECIPIENTS_FILE}, set {IDENTITY_VAR}"
+                );
+            }
+            Self::Passphrase(passphrase) => {
+                Encryptor::with_user_passphrase(passphrase.clone())
+            }
+            Self::Identities(identities) => {
+                let mut all: Vec<_> = identities
+                    .iter()
+                    .map(x25519::Identity::to_public)
+                    .collect();
+                for recipient in recipients {
+                    let key = recipient.to_string();
+                    if !all.iter().any(|r| r.to_string() == key) {
+                        all.push(recipient.clone());
+                    }
+                }
+                Encryptor::with_recipients(
+                    all.iter().map(|r| r as &dyn age::Recipient),
+                )?
+            }
+        };
+
+        let mut out = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut out)?;
+        writer.write_all(plaintext.as_bytes())?;
+        writer.finish()?;
+
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<String> {
+        let decryptor = Decryptor::new(ciphertext)?;
+        let mut reader = match self {
+            Self::Passphrase(passphrase) => {
+                let identity = age::scrypt::Identity::new(passphrase.clone());
+                decryptor
+                    .decrypt(iter::once(&identity as &dyn age::Identity))?
+            }
+            Self::Identities(identities) => decryptor
+                .decrypt(identities.iter().map(|i| i as &dyn age::Identity))?,
+        };
+
+        let mut plaintext = String::new();
+        reader.read_to_string(&mut plaintext)?;
+
+        Ok(plaintext)
+    }
+}
+
+/// The key and the decrypted content of a secrets file, so that the
+/// passphrase is asked for at most once, and the file is only decrypted
+/// again when it changes.
+struct Cached {
+    key: Key,
+    modified: SystemTime,
+    secrets: Table,
+}
+
+fn cache() -> &'static Mutex<HashMap<PathBuf, Cached>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Cached>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// Read and decrypt the secrets file in the project root, if there is one.
+pub fn read_secrets(root_dir: &Path) -> Result<Option<Table>> {
+    let path = root_dir.join(SECRETS_FILE);
+    let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+        return Ok(None);
+    };
+
+    let mut cache = cache().lock().expect("not poisoned");
+    let key = match cache.get(&path) {
+        Some(c) if c.modified == modified => {
+            return Ok(Some(c.secrets.clone()));
+        }
+        Some(c) => c.key.clone(),
+        None => Key::from_env(false)?,
+    };
+
+    debug!("# Decrypting {SECRETS_FILE}");
+    let secrets = decrypt_file(&path, &key)?;
+    let secrets = toml::from_str::<Table>(&secrets)
+        .with_context(|| format!("When reading {SECRETS_FILE}"))?;
+
+    cache.insert(
+        path,
+        Cached {
+            key,
+            modified,
+            secrets: secrets.clone(),
+        },
+    );
+
+    Ok(Some(secrets))
+}
+
+/// Read the `age1...` lines of the recipients file in the project root, if
+/// there is one.
+fn read_recipients(root_dir: &Path) -> Result<Vec<x25519::Recipient>> {
+    let Ok(content) = fs::read_to_string(root_dir.join(RECIPIENTS_FILE)) else {
+        return Ok(Vec::new());
+    };
+
+    parse_recipients(&content)
+        .with_context(|| format!("When reading {RECIPIENTS_FILE}"))
+}
+
+fn parse_recipients(content: &str) -> Result<Vec<x25519::Recipient>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<x25519::Recipient>()
+                .map_err(|e| anyhow::anyhow!("Invalid recipient: {e}"))
+        })
+        .collect()
+}
+
+fn decrypt_file(path: &Path, key: &Key) -> Result<String> {
+    key.decrypt(&fs::read(path)?)
+        .with_context(|| format!("When decrypting {SECRETS_FILE}"))
+}
+
+/// Remove values that were decrypted from a secrets file, so that they are
+/// never stored in plain text.
+pub fn without_secrets(vars: &Table) -> Table {
+    let cache = cache().lock().expect("not poisoned");
+    let mut secrets = Vec::new();
+    for c in cache.values() {
+        c.secrets
+            .values()
+            .for_each(|v| collect_strings(v, &mut secrets));
+    }
+
+    vars.iter()
+        .filter(|(key, value)| {
+            let mut strings = Vec::new();
+            collect_strings(value, &mut strings);
+            let secret = strings.iter().any(|s| secrets.contains(s));
+            if secret {
+                warn!("# Not storing '{key}', it contains a secret");
+            }
+            !secret
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn collect_strings<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+    match value {
+        Value::String(s) if !s.is_empty() => out.push(s),
+        Value::Array(arr) => arr.iter().for_each(|v| collect_strings(v, out)),
+        Value::Table(t) => t.values().for_each(|v| collect_strings(v, out)),
+        _ => (),
+    }
+}
+
+/// Decrypt the secrets file to a temporary file, open it in `$EDITOR`, and
+/// encrypt it again. The file is created if it doesn't exist.
+pub fn edit_secrets(root_dir: &Path) -> Result<()> {
+    let path = root_dir.join(SECRETS_FILE);
+    let exists = path.exists();
+    let key = Key::from_env(!exists)?;
+    let original = if exists {
+        decrypt_file(&path, &key)?
+    } else {
+        String::new()
+    };
+
+    let tmp = env::temp_dir().join(format!(
+        "hitman-secrets-{}.toml",
+        iter::repeat_with(fastrand::alphanumeric)
+            .take(12)
+            .collect::<String>()
+    ));
+    write_private(&tmp, original.as_bytes())?;
+
+    let edited = run_editor(&tmp).and_then(|()| {
+        let edited = fs::read_to_string(&tmp)?;
+        toml::from_str::<Table>(&edited).with_context(|| {
+            format!("Invalid TOML, {SECRETS_FILE} not saved")
+        })?;
+        Ok(edited)
+    });
+    let _ = fs::remove_file(&tmp);
+    let edited = edited?;
+
+    if edited == original {
+        warn!("# No changes");
+        return Ok(());
+    }
+
+    let recipients = read_recipients(root_dir)?;
+    fs::write(&path, key.encrypt(&edited, &recipients)?)?;
+    warn!("# Saved {SECRETS_FILE}");
+
+    Ok(())
+}
+
+/// Add a single value to the secrets file, globally or for a target.
+pub fn set_secret(
+    root_dir: &Path,
+    target: Option<&str>,
+    name: &str,
+    value: &str,
+) -> Result<()> {
+    let path = root_dir.join(SECRETS_FILE);
+    let exists = path.exists();
+    let key = Key::from_env(!exists)?;
+    let mut secrets = if exists {
+        toml::from_str::<Table>(&decrypt_file(&path, &key)?)
+            .with_context(|| format!("When reading {SECRETS_FILE}"))?
+    } else {
+        Table::new()
+    };
+
+    let table = match target {
+        Some(target) => match secrets
+            .entry(target)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(t) => t,
+            other => bail!("Not a target in {SECRETS_FILE}: {other}"),
+        },
+        None => &mut secrets,
+    };
+    table.insert(name.to_string(), Value::String(value.to_string()));
+
+    let recipients = read_recipients(root_dir)?;
+    let content = toml::to_string_pretty(&secrets)?;
+    fs::write(&path, key.encrypt(&content, &recipients)?)?;
+    warn!("# Saved {name} in {SECRETS_FILE}");
+
+    Ok(())
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // The editor can have arguments, like `code --wait`
+    let mut args = editor.split_whitespace();
+    let program = args.next().context("No editor set")?;
+    let status = Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()
+        .with_context(|| format!("When running {editor}"))?;
+
+    if !status.success() {
+        bail!("Editor failed ({status}), {SECRETS_FILE} not saved");
+    }
+
+    Ok(())
+}
+
+/// Write a file that only the current user can read.
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypts_with_identities() {
+        let identity = x25519::Identity::generate();
+        let content = format!(
+            "# created: today\n{}\n",
+            age::secrecy::ExposeSecret::expose_secret(&identity.to_string())
+        );
+        let key = Key::parse_identities(&content).unwrap();
+
+        let encrypted = key.encrypt("token = 'apple'", &[]).unwrap();
+        assert!(!String::from_utf8_lossy(&encrypted).contains("apple"));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "token = 'apple'");
+
+        let other = Key::Identities(vec![x25519::Identity::generate()]);
+        assert!(other.decrypt(&encrypted).is_err());
+        assert!(Key::parse_identities("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn encrypts_for_all_recipients() {
+        let first = x25519::Identity::generate();
+        let second = x25519::Identity::generate();
+        let recipients = parse_recipients(&format!(
+            "# team\n{}\n{}\n",
+            first.to_public(),
+            second.to_public()
+        ))
+        .unwrap();
+        let first = Key::Identities(vec![first]);
+        let second = Key::Identities(vec![second]);
+
+        let encrypted = first.encrypt("token = 'apple'", &recipients).unwrap();
+        assert_eq!(second.decrypt(&encrypted).unwrap(), "token = 'apple'");
+
+        // Edited by the second one, the first one can still decrypt it
+        let edited = second.encrypt("token = 'pear'", &recipients).unwrap();
+        assert_eq!(first.decrypt(&edited).unwrap(), "token = 'pear'");
+
+        let passphrase = Key::Passphrase("hunter2".to_string().into());
+        assert!(passphrase.encrypt("token = 'apple'", &recipients).is_err());
+    }
+}
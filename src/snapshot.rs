@@ -0,0 +1,393 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde_json::{json, Map, Value as JsonValue};
+use serde_json_path::JsonPath;
+use toml::Value;
+
+use crate::{
+    assertion::CheckResult,
+    prompt::{get_boolean, set_boolean},
+    request::HitmanResponse,
+    resolve::Resolved,
+    scope::Scope,
+    util::truncate,
+};
+
+/// Replaces values that are ignored by the `_snapshot` section.
+const IGNORED: &str = "<ignored>";
+
+/// Headers included in a snapshot, unless configured otherwise.
+const DEFAULT_HEADERS: &[&str] = &["content-type"];
+
+/// Compare responses with snapshots, even if the request has no
+/// `_snapshot` section.
+pub fn set_snapshot_mode(enable: bool) {
+    set_boolean("snapshot", enable);
+}
+
+pub fn is_snapshot_mode() -> bool {
+    get_boolean("snapshot")
+}
+
+/// Replace existing snapshots with the next response.
+pub fn set_accept_mode(enable: bool) {
+    set_boolean("snapshot_accept", enable);
+}
+
+pub fn is_accept_mode() -> bool {
+    get_boolean("snapshot_accept")
+}
+
+/// A normalized response, stored next to the request file.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub value: JsonValue,
+
+    /// Differences from the stored snapshot, or `None` if the snapshot
+    /// was just saved.
+    pub differences: Option<Vec<Difference>>,
+}
+
+impl Snapshot {
+    /// Normalize the response according to the `_snapshot` section.
+    pub fn take(
+        resolved: &Resolved,
+        response: &HitmanResponse,
+        scope: &Scope,
+    ) -> Result<Self> {
+        let normalization =
+            Normalization::parse("_snapshot", scope.snapshot())?;
+
+        Ok(Self {
+            path: resolved.snapshot_path(),
+            value: normalization.apply(response)?,
+            differences: None,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Option<JsonValue>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(
+                serde_json::from_str(&content)
+                    .with_context(|| format!("When reading {path:?}"))?,
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.value)?;
+        fs::write(&self.path, content + "\n")?;
+
+        Ok(())
+    }
+
+    pub fn check(&self) -> Option<CheckResult> {
+        self.differences.as_ref().map(|diffs| {
+            CheckResult::new(
+                "response matches snapshot".to_string(),
+                (!diffs.is_empty())
+                    .then(|| format!("{} differences", diffs.len())),
+            )
+        })
+    }
+
+    pub fn print(&self) {
+        match &self.differences {
+            None => warn!("# Snapshot saved to {}", self.path.display()),
+            Some(diffs) => {
+                for diff in diffs {
+                    warn!("# {}", truncate(&diff.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// Compare the response with the stored snapshot, if snapshots are enabled
+/// for this request.
+///
+/// The snapshot is saved if it doesn't exist yet, or if running in accept
+/// mode.
+pub fn verify_snapshot(
+    resolved: &Resolved,
+    response: &HitmanResponse,
+    scope: &Scope,
+) -> Result<Option<Snapshot>> {
+    if !(scope.snapshot().is_some() || is_snapshot_mode() || is_accept_mode()) {
+        return Ok(None);
+    }
+
+    let mut snapshot = Snapshot::take(resolved, response, scope)?;
+
+    match Snapshot::load(&snapshot.path)? {
+        Some(expected) if !is_accept_mode() => {
+            snapshot.differences = Some(diff(&expected, &snapshot.value));
+        }
+        _ => snapshot.save()?,
+    }
+
+    Ok(Some(snapshot))
+}
+
+/// How a response is normalized before it is compared, configured by a
+/// section like `_snapshot`.
+pub struct Normalization {
+    pub headers: Vec<String>,
+    pub ignore: Vec<String>,
+}
+
+impl Normalization {
+    pub fn parse(section: &str, value: Option<&Value>) -> Result<Self> {
+        let mut config = Self {
+            headers: DEFAULT_HEADERS.iter().map(ToString::to_string).collect(),
+            ignore: Vec::new(),
+        };
+
+        match value {
+            None | Some(Value::Boolean(_)) => (),
+            Some(Value::Table(table)) => {
+                for (key, value) in table {
+                    match key.as_str() {
+                        "headers" => {
+                            config.headers = string_list(section, key, value)?;
+                        }
+                        "ignore" => {
+                            config.ignore = string_list(section, key, value)?;
+                        }
+                        _ => bail!("Invalid {section} option: {key}"),
+                    }
+                }
+            }
+            Some(_) => bail!("Invalid {section} section"),
+        }
+
+        Ok(config)
+    }
+
+    /// Status, selected headers and body of the response, with ignored
+    /// values replaced.
+    pub fn apply(&self, response: &HitmanResponse) -> Result<JsonValue> {
+        let mut headers = Map::new();
+        for name in &self.headers {
+            if let Some(value) = response.headers.get(name) {
+                headers.insert(name.to_lowercase(), value.to_str()?.into());
+            }
+        }
+
+        let body = match response.json() {
+            Some(json) => mask(&json, &self.ignore)?,
+            None if response.body.is_empty() => JsonValue::Null,
+            None => response.body.clone().into(),
+        };
+
+        Ok(json!({
+            "status": response.status.as_u16(),
+            "headers": headers,
+            "body": body,
+        }))
+    }
+}
+
+fn string_list(section: &str, key: &str, value: &Value) -> Result<Vec<String>> {
+    let Value::Array(arr) = value else {
+        bail!("Invalid {section} option: {key} must be a list");
+    };
+
+    arr.iter()
+        .map(|v| match v {
+            Value::String(s) => Ok(s.clone()),
+            other => bail!("Invalid {section} option: {key} = {other}"),
+        })
+        .collect()
+}
+
+/// Replace all values matching one of the paths.
+fn mask(json: &JsonValue, paths: &[String]) -> Result<JsonValue> {
+    let mut ignored = Vec::new();
+    for path in paths {
+        let jsonpath = JsonPath::parse(path)
+            .map_err(|err| anyhow!("Invalid jsonpath: {err}"))?;
+        ignored.extend(jsonpath.query(json).into_iter().map(ptr::from_ref));
+    }
+
+    Ok(replace(json, &ignored))
+}
+
+fn replace(value: &JsonValue, ignored: &[*const JsonValue]) -> JsonValue {
+    if ignored.iter().any(|p| ptr::eq(*p, value)) {
+        return IGNORED.into();
+    }
+
+    match value {
+        JsonValue::Array(arr) => {
+            arr.iter().map(|v| replace(v, ignored)).collect()
+        }
+        JsonValue::Object(obj) => obj
+            .iter()
+            .map(|(k, v)| (k.clone(), replace(v, ignored)))
+            .collect(),
+        other => other.clone(),
+    }
+}
+
+/// A single difference between a snapshot and a new response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Added {
+        path: String,
+        value: JsonValue,
+    },
+    Removed {
+        path: String,
+        value: JsonValue,
+    },
+    Changed {
+        path: String,
+        expected: JsonValue,
+        actual: JsonValue,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added { path, value } => write!(f, "+ {path}: {value}"),
+            Self::Removed { path, value } => write!(f, "- {path}: {value}"),
+            Self::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(f, "~ {path}: {expected} -> {actual}"),
+        }
+    }
+}
+
+/// Structural difference between two JSON values.
+pub fn diff(expected: &JsonValue, actual: &JsonValue) -> Vec<Difference> {
+    let mut diffs = Vec::new();
+    diff_at("$", expected, actual, &mut diffs);
+    diffs
+}
+
+fn diff_at(
+    path: &str,
+    expected: &JsonValue,
+    actual: &JsonValue,
+    diffs: &mut Vec<Difference>,
+) {
+    match (expected, actual) {
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            for (key, value) in a {
+                let path = child_path(path, key);
+                match b.get(key) {
+                    Some(other) => diff_at(&path, value, other, diffs),
+                    None => diffs.push(Difference::Removed {
+                        path,
+                        value: value.clone(),
+                    }),
+                }
+            }
+            for (key, value) in b.iter().filter(|(k, _)| !a.contains_key(*k)) {
+                diffs.push(Difference::Added {
+                    path: child_path(path, key),
+                    value: value.clone(),
+                });
+            }
+        }
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                let path = format!("{path}[{i}]");
+                match (a.get(i), b.get(i)) {
+                    (Some(x), Some(y)) => diff_at(&path, x, y, diffs),
+                    (Some(x), None) => diffs.push(Difference::Removed {
+                        path,
+                        value: x.clone(),
+                    }),
+                    (None, Some(y)) => diffs.push(Difference::Added {
+                        path,
+                        value: y.clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (a, b) if a != b => diffs.push(Difference::Changed {
+            path: path.to_string(),
+            expected: a.clone(),
+            actual: b.clone(),
+        }),
+        _ => (),
+    }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    let simple = !key.is_empty()
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if simple {
+        format!("{path}.{key}")
+    } else {
+        format!("{path}['{key}']")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_structural_differences() {
+        let expected = json!({
+            "id": 1,
+            "tags": ["a", "b"],
+            "owner": { "name": "Bob", "x-y": 1 },
+        });
+        let actual = json!({
+            "id": 1,
+            "tags": ["a"],
+            "owner": { "name": "Alice", "x-y": 1 },
+            "color": "red",
+        });
+
+        assert_eq!(
+            diff(&expected, &actual)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                r#"~ $.owner.name: "Bob" -> "Alice""#,
+                r#"- $.tags[1]: "b""#,
+                r#"+ $.color: "red""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn masks_ignored_values() {
+        let json = json!([
+            { "id": 1, "name": "Apple", "created": "2024-01-01" },
+            { "id": 2, "name": "Pear", "created": "2024-01-02" },
+        ]);
+
+        let masked =
+            mask(&json, &["$.*.id".into(), "$[1].created".into()]).unwrap();
+
+        assert_eq!(
+            masked,
+            json!([
+                { "id": IGNORED, "name": "Apple", "created": "2024-01-01" },
+                { "id": IGNORED, "name": "Pear", "created": IGNORED },
+            ])
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context};
 use httparse::Status;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
@@ -9,10 +9,15 @@ use std::{
     fs::read_to_string,
     str::{self, FromStr},
 };
+use thiserror::Error;
+use toml::Value;
 
 use crate::{
+    dynamic::is_dynamic,
+    reference::is_reference,
     request::{find_args, HitmanBody, HitmanRequest},
     resolve::{Resolved, ResolvedAs},
+    scope::Scope,
 };
 
 #[derive(Debug, PartialEq, Eq)]
@@ -30,6 +35,7 @@ pub use Substitution::{Complete, ValueMissing};
 pub fn prepare_request(
     resolved: &Resolved,
     vars: &HashMap<String, SubstitutionValue<String>>,
+    max_depth: usize,
 ) -> anyhow::Result<Substitution<HitmanRequest>> {
     // FIXME This is still doing too much:
     // - Substituting placeholders in the raw input text
@@ -38,8 +44,16 @@ pub fn prepare_request(
     // - Generating variables for GraphQL (quite different for raw text
     //   substitution)
 
+    let graphql_path = match &resolved.resolved_as {
+        ResolvedAs::Simple { .. } => None,
+        ResolvedAs::GraphQL { graphql_path, .. } => Some(graphql_path),
+        ResolvedAs::Flow { .. } => {
+            bail!("A flow can't be prepared as a single request")
+        }
+    };
+
     let input = read_to_string(resolved.http_file())?;
-    let buf = match substitute(&input, vars)? {
+    let buf = match substitute_nested(&input, vars, max_depth)? {
         Complete(buf) => buf,
         ValueMissing {
             key,
@@ -67,8 +81,8 @@ pub fn prepare_request(
     let method = Method::from_str(method)?;
     let url = Url::parse(url)?;
 
-    let body = match &resolved.resolved_as {
-        ResolvedAs::GraphQL { graphql_path, .. } => {
+    let body = match graphql_path {
+        Some(graphql_path) => {
             let body = read_to_string(graphql_path)?;
             let args = find_args(graphql_path)?;
 
@@ -92,7 +106,12 @@ pub fn prepare_request(
 
                     match value {
                         SubstitutionValue::Single(item) => {
-                            map.insert(key.name, serde_json::to_value(item)?);
+                            let item = unescape(item);
+                            let value = match as_json_object(&item) {
+                                Some(object) if key.object => object,
+                                _ => serde_json::to_value(item)?,
+                            };
+                            map.insert(key.name, value);
                         }
                         SubstitutionValue::Multiple(items) => {
                             map.insert(key.name, serde_json::to_value(items)?);
@@ -108,7 +127,7 @@ pub fn prepare_request(
                 })
             }
         }
-        ResolvedAs::Simple { .. } => match parse_result {
+        None => match parse_result {
             Status::Complete(offset) => Some(HitmanBody::Plain {
                 body: buf[offset..].to_string(),
             }),
@@ -135,23 +154,159 @@ pub fn prepare_request(
         url,
         method,
         body,
+        default_headers: Vec::new(),
     }))
 }
 
+/// The headers in the `_headers` section, that are added to every request.
+/// A header set to `false` is left out, so that it isn't inherited.
+pub fn default_headers(
+    scope: &Scope,
+) -> anyhow::Result<Vec<(HeaderName, String)>> {
+    let Some(section) = scope.headers() else {
+        return Ok(Vec::new());
+    };
+    let Value::Table(table) = section else {
+        bail!("Invalid _headers section");
+    };
+
+    let mut headers = Vec::new();
+    for (name, value) in table {
+        let header_name = HeaderName::from_str(name)
+            .with_context(|| format!("Invalid header in _headers: {name}"))?;
+        match value {
+            Value::String(template) => {
+                headers.push((header_name, template.clone()));
+            }
+            Value::Boolean(false) => (),
+            other => bail!("Invalid value in _headers for {name}: {other}"),
+        }
+    }
+
+    Ok(headers)
+}
+
+/// Add default headers that the template doesn't set itself, substituting
+/// placeholders in their values.
+pub fn add_default_headers(
+    mut req: HitmanRequest,
+    defaults: &[(HeaderName, String)],
+    vars: &HashMap<String, SubstitutionValue<String>>,
+    max_depth: usize,
+) -> anyhow::Result<Substitution<HitmanRequest>> {
+    for (name, template) in defaults {
+        if req.headers.contains_key(name) {
+            continue;
+        }
+
+        let value = match substitute_nested(template, vars, max_depth)? {
+            Complete(value) => value,
+            ValueMissing {
+                key,
+                fallback,
+                multiple,
+            } => {
+                return Ok(ValueMissing {
+                    key,
+                    fallback,
+                    multiple,
+                })
+            }
+        };
+
+        let value = value.strip_suffix('\n').unwrap_or(&value);
+        req.headers.insert(name, HeaderValue::from_str(value)?);
+        req.default_headers.push(name.clone());
+    }
+
+    Ok(Complete(req))
+}
+
+/// Escape the delimiters in a value, so that it is used as it is, instead of
+/// being substituted again, like a table as JSON.
+pub fn literal(value: &str) -> String {
+    value.replace("{{", "\\{{").replace("}}", "\\}}")
+}
+
+/// The value as it is, for values that are not substituted, like GraphQL
+/// variables.
+fn unescape(value: &str) -> String {
+    value.replace("\\{{", "{{").replace("\\}}", "}}")
+}
+
+/// A value that is a JSON object, like a table from the config.
+fn as_json_object(s: &str) -> Option<serde_json::Value> {
+    serde_json::from_str(s)
+        .ok()
+        .filter(serde_json::Value::is_object)
+}
+
 #[derive(Debug, Clone)]
 pub enum SubstitutionValue<T> {
     Single(T),
     Multiple(Vec<T>),
 }
 
+/// How many levels values can refer to other values, unless configured
+/// with `_max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 20;
+
+/// Raised when nested substitution never ends, because a value refers to
+/// itself, or goes too deep.
+#[derive(Debug, Error)]
+pub enum NestingError {
+    #[error("Cyclic reference: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+
+    #[error("Nested substitution is too deep: {}", .0.join(" -> "))]
+    TooDeep(Vec<String>),
+}
+
+impl NestingError {
+    /// Add the file that each value came from, like `token (hitman.toml)`.
+    #[must_use]
+    pub fn with_sources(self, scope: &Scope) -> Self {
+        let annotate = |chain: Vec<String>| {
+            chain
+                .into_iter()
+                .map(|key| match scope.source(&key) {
+                    Some(source) => format!("{key} ({source})"),
+                    None => key,
+                })
+                .collect()
+        };
+
+        match self {
+            Self::Cycle(chain) => Self::Cycle(annotate(chain)),
+            Self::TooDeep(chain) => Self::TooDeep(annotate(chain)),
+        }
+    }
+}
+
 pub fn substitute(
     input: &str,
     vars: &HashMap<String, SubstitutionValue<String>>,
+) -> anyhow::Result<Substitution<String>> {
+    substitute_nested(input, vars, DEFAULT_MAX_DEPTH)
+}
+
+/// Substitute values, where values can contain references to other values,
+/// up to `max_depth` levels deep.
+pub fn substitute_nested(
+    input: &str,
+    vars: &HashMap<String, SubstitutionValue<String>>,
+    max_depth: usize,
 ) -> anyhow::Result<Substitution<String>> {
     let mut output = String::new();
 
-    for line in input.lines() {
-        let res = match substitute_line(line, vars)? {
+    for (i, line) in input.lines().enumerate() {
+        let res = match substitute_line(
+            line,
+            i + 1,
+            vars,
+            &mut Vec::new(),
+            max_depth,
+        )? {
             Complete(l) => l,
             m @ ValueMissing { .. } => return Ok(m),
         };
@@ -162,39 +317,72 @@ pub fn substitute(
     Ok(Complete(output))
 }
 
+/// Substitute values in a line, and then in each of the values. The keys
+/// of the values being substituted are kept in `chain`.
 fn substitute_line(
     line: &str,
+    line_no: usize,
     vars: &HashMap<String, SubstitutionValue<String>>,
+    chain: &mut Vec<String>,
+    max_depth: usize,
 ) -> anyhow::Result<Substitution<String>> {
     let mut output = String::new();
     let mut slice = line;
     loop {
-        match slice.find("{{") {
+        match next_token(slice) {
             None => {
-                if slice.contains("}}") {
-                    bail!("Syntax error");
-                }
                 output.push_str(slice);
                 break;
             }
-            Some(pos) => {
+            Some((pos, Token::Escaped(text))) => {
+                output.push_str(&slice[..pos]);
+                output.push_str(text);
+                slice = &slice[pos + 3..];
+            }
+            Some((_, Token::Close)) => {
+                return Err(syntax_error("unmatched '}}'", line_no, chain));
+            }
+            Some((pos, Token::Open)) => {
                 output.push_str(&slice[..pos]);
                 slice = &slice[pos..];
 
                 let Some(end) = slice.find("}}").map(|i| i + 2) else {
-                    bail!("Syntax error");
+                    return Err(syntax_error("unmatched '{{'", line_no, chain));
                 };
 
-                let rep = match substitute_inner(&slice[2..end - 2], vars) {
-                    Complete(v) => v,
-                    m @ ValueMissing { .. } => return Ok(m),
-                };
+                let (key, rep) =
+                    match substitute_inner(&slice[2..end - 2], vars) {
+                        Complete(v) => v,
+                        ValueMissing {
+                            key,
+                            fallback,
+                            multiple,
+                        } => {
+                            return Ok(ValueMissing {
+                                key,
+                                fallback,
+                                multiple,
+                            })
+                        }
+                    };
+
+                let cyclic = chain.contains(&key);
+                chain.push(key);
+                if cyclic {
+                    return Err(NestingError::Cycle(chain.clone()).into());
+                }
+                if chain.len() > max_depth {
+                    return Err(NestingError::TooDeep(chain.clone()).into());
+                }
 
                 // Nested substitution
-                let rep = match substitute_line(&rep, vars)? {
+                let rep = match substitute_line(
+                    &rep, line_no, vars, chain, max_depth,
+                )? {
                     Complete(v) => v,
                     m @ ValueMissing { .. } => return Ok(m),
                 };
+                chain.pop();
                 output.push_str(&rep);
 
                 slice = &slice[end..];
@@ -205,6 +393,48 @@ fn substitute_line(
     Ok(Complete(output))
 }
 
+/// Placeholder delimiters, or an escaped delimiter like `\{{`, which is
+/// kept as literal text.
+enum Token {
+    Open,
+    Close,
+    Escaped(&'static str),
+}
+
+fn next_token(s: &str) -> Option<(usize, Token)> {
+    let bytes = s.as_bytes();
+    (0..bytes.len()).find_map(|i| {
+        let rest = &bytes[i..];
+        let token = if rest.starts_with(b"\\{{") {
+            Token::Escaped("{{")
+        } else if rest.starts_with(b"\\}}") {
+            Token::Escaped("}}")
+        } else if rest.starts_with(b"{{") {
+            Token::Open
+        } else if rest.starts_with(b"}}") {
+            Token::Close
+        } else {
+            return None;
+        };
+        Some((i, token))
+    })
+}
+
+fn syntax_error(
+    problem: &str,
+    line_no: usize,
+    chain: &[String],
+) -> anyhow::Error {
+    let hint = "use \\{{ and \\}} for literal braces";
+    match chain.last() {
+        None => anyhow!("Syntax error on line {line_no}: {problem} ({hint})"),
+        Some(key) => anyhow!(
+            "Syntax error in value of '{key}', used on line {line_no}: \
+            {problem} ({hint})"
+        ),
+    }
+}
+
 #[derive(Debug)]
 struct Pair {
     open: String,
@@ -265,10 +495,28 @@ fn parse_list_syntax(s: &str) -> anyhow::Result<ListSyntax> {
     })
 }
 
+/// Substitute the contents of a placeholder, and return the key along with
+/// the substituted value.
 fn substitute_inner(
     inner: &str,
     vars: &HashMap<String, SubstitutionValue<String>>,
-) -> Substitution<std::string::String> {
+) -> Substitution<(String, String)> {
+    // References to stored responses are looked up as a whole, including
+    // any `||` alternatives, and so are dynamic values with arguments.
+    let trimmed = inner.trim();
+    if is_reference(trimmed) || is_dynamic(trimmed) {
+        return match vars.get(trimmed) {
+            Some(SubstitutionValue::Single(s)) => {
+                Complete((trimmed.to_string(), s.clone()))
+            }
+            _ => ValueMissing {
+                key: trimmed.to_string(),
+                fallback: None,
+                multiple: false,
+            },
+        };
+    }
+
     let mut parts = inner.split('|');
 
     // Only valid with ascii_alphabetic, ascii_digit or underscores in key name
@@ -277,12 +525,19 @@ fn substitute_inner(
     };
 
     let key = parts.next().unwrap_or("").trim();
-    let parsed_key = key
+    let mut parsed_key = key
         .chars()
         .skip_while(|c| !valid_character(c))
         .take_while(valid_character)
         .collect::<String>();
 
+    // A path into a table, array or selected list item, like `db.host`,
+    // `hosts[0]` or `apple_id.name`
+    if let Some(pos) = key.find(parsed_key.as_str()) {
+        let rest = &key[pos + parsed_key.len()..];
+        parsed_key.push_str(&rest[..path_len(rest)]);
+    }
+
     let fallback = parts.next().map(str::trim);
 
     let list_syntax = parse_list_syntax(key);
@@ -311,15 +566,42 @@ fn substitute_inner(
     });
 
     match substitution {
-        Some(s) => Complete(s),
+        Some(s) => Complete((parsed_key, s)),
         None => ValueMissing {
+            multiple: list_syntax.is_ok() && !parsed_key.contains(['.', '[']),
             key: parsed_key,
             fallback: fallback.map(ToString::to_string),
-            multiple: list_syntax.is_ok(),
         },
     }
 }
 
+/// The length of a path after a key, like `.host`, `[0]` or `[0].name`.
+/// A JSON-path into a selected list item, like `apple_id._raw.$.items[0]`,
+/// is taken as it is.
+fn path_len(rest: &str) -> usize {
+    if rest.starts_with("._raw.$") {
+        return rest.len();
+    }
+
+    if let Some(r) = rest.strip_prefix('.') {
+        let len = r
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(r.len());
+        return if len == 0 {
+            0
+        } else {
+            1 + len + path_len(&r[len..])
+        };
+    }
+
+    let is_index =
+        |i: &str| !i.is_empty() && i.bytes().all(|c| c.is_ascii_digit());
+    match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        Some((i, r)) if is_index(i) => i.len() + 2 + path_len(r),
+        _ => 0,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -618,4 +900,166 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn requests_reference_as_a_whole() {
+        let vars = create_vars();
+        let res = substitute(
+            "Authorization: Bearer {{ @refresh.$.token || @login.$.token }}",
+            &vars,
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            ValueMissing {
+                key: "@refresh.$.token || @login.$.token".to_string(),
+                fallback: None,
+                multiple: false,
+            }
+        );
+    }
+
+    #[test]
+    fn substitutes_field_of_selected_item() {
+        let mut vars = create_vars();
+        let res = substitute("{{ apple_id._raw.$.items[0].id }}", &vars);
+
+        assert_eq!(
+            res.unwrap(),
+            ValueMissing {
+                key: "apple_id._raw.$.items[0].id".to_string(),
+                fallback: None,
+                multiple: false,
+            }
+        );
+
+        vars.insert(
+            "apple_id.name".to_string(),
+            SubstitutionValue::Single("Granny Smith".to_string()),
+        );
+        let res = substitute("name={{apple_id.name}}\n", &vars).unwrap();
+
+        assert_eq!(res, Complete("name=Granny Smith\n".to_string()));
+    }
+
+    #[test]
+    fn substitutes_nested_values() {
+        let mut vars = create_vars();
+        vars.insert(
+            "hosts[1]".to_string(),
+            SubstitutionValue::Single("b.com".to_string()),
+        );
+        let res = substitute("{{ hosts[1] }} {{ db.host }}", &vars);
+
+        assert_eq!(
+            res.unwrap(),
+            ValueMissing {
+                key: "db.host".to_string(),
+                fallback: None,
+                multiple: false,
+            }
+        );
+
+        let res = substitute("{{ hosts[1] }} {{ list [0] }}", &vars).unwrap();
+
+        assert_eq!(res, Complete("b.com 10203\n".to_string()));
+    }
+
+    #[test]
+    fn substitutes_nested_values_in_quotes_and_lists() {
+        let mut vars = create_vars();
+        vars.insert(
+            "db.host".to_string(),
+            SubstitutionValue::Single("db.local".to_string()),
+        );
+        vars.insert(
+            "items.ids".to_string(),
+            SubstitutionValue::Multiple(vec!["1".to_string(), "2".to_string()]),
+        );
+
+        let res = substitute("{{ \"db.host\" }} [{{ items.ids [, ] }}]", &vars);
+        assert_eq!(res.unwrap(), Complete("\"db.local\" [1, 2]\n".into()));
+
+        let res = substitute("{{ items.names [, ] }}", &vars);
+        assert_eq!(
+            res.unwrap(),
+            ValueMissing {
+                key: "items.names".to_string(),
+                fallback: None,
+                multiple: false,
+            }
+        );
+
+        let res = substitute("{{ \"hosts[0].name\" }}", &vars);
+        assert_eq!(
+            res.unwrap(),
+            ValueMissing {
+                key: "hosts[0].name".to_string(),
+                fallback: None,
+                multiple: false,
+            }
+        );
+    }
+
+    #[test]
+    fn detects_cyclic_references() {
+        let mut vars = create_vars();
+        for (key, value) in [("a", "{{b}}"), ("b", "x {{ a }}")] {
+            vars.insert(key.into(), SubstitutionValue::Single(value.into()));
+        }
+
+        let err = substitute("{{a}}", &vars).unwrap_err();
+
+        assert_eq!(err.to_string(), "Cyclic reference: a -> b -> a");
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let mut vars = create_vars();
+        for (key, value) in [("a", "{{b}}"), ("b", "{{nested}}")] {
+            vars.insert(key.into(), SubstitutionValue::Single(value.into()));
+        }
+
+        let res = substitute_nested("{{a}}", &vars, 4).unwrap();
+        assert_eq!(res, Complete("the answer is 42\n".to_string()));
+
+        let err = substitute_nested("{{a}}", &vars, 3).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Nested substitution is too deep: a -> b -> nested -> integer"
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_braces() {
+        let vars = create_vars();
+        let res =
+            substitute("<p>\\{{ name \\}}</p> {{url}}\n\\}} \\{{\n", &vars)
+                .unwrap();
+
+        assert_eq!(
+            res,
+            Complete("<p>{{ name }}</p> example.com\n}} {{\n".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_line_of_syntax_error() {
+        let mut vars = create_vars();
+        vars.insert(
+            "broken".to_string(),
+            SubstitutionValue::Single("a }} b".to_string()),
+        );
+
+        let err = substitute("foo\nbar {{url\n", &vars).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Syntax error on line 2: unmatched '{{'"));
+
+        let err = substitute("{{ broken }}\n", &vars).unwrap_err();
+        assert!(err.to_string().starts_with(
+            "Syntax error in value of 'broken', used on line 1: unmatched '}}'"
+        ));
+    }
 }
@@ -0,0 +1,440 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::warn;
+
+use crate::{
+    assertion::CheckResult,
+    env::{find_available_requests, get_target, load_env},
+    flow::{run_flow_with, Flow, StepReport},
+    request::make_request_with,
+    requires::RequestChain,
+    resolve::{resolve_path, Resolved, ResolvedAs},
+    scope::Scope,
+};
+
+/// The results of testing a single request or flow.
+pub struct TestCase {
+    pub name: String,
+    pub elapsed: Duration,
+    pub checks: Vec<CheckResult>,
+    pub error: Option<String>,
+}
+
+impl TestCase {
+    /// The number of test cases reported, one for each check, and one for
+    /// the error, if the request failed.
+    pub fn tests(&self) -> usize {
+        self.checks.len() + usize::from(self.error.is_some())
+    }
+
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| !c.passed()).count()
+            + usize::from(self.error.is_some())
+    }
+}
+
+/// Run all requests and flows with assertions or snapshots, found in the
+/// given paths.
+pub async fn run_tests(
+    target: Option<&str>,
+    paths: &[PathBuf],
+    options: &[(String, String)],
+) -> Result<Vec<TestCase>> {
+    let mut cases = Vec::new();
+
+    for file_path in collect_requests(paths)? {
+        let resolved = resolve_path(&file_path)?;
+        let target = target.map_or_else(
+            || get_target(&resolved.root_dir),
+            ToString::to_string,
+        );
+
+        if !has_checks(&target, &resolved, options)? {
+            continue;
+        }
+
+        let name = file_path
+            .strip_prefix(&resolved.root_dir)
+            .unwrap_or(&file_path)
+            .display()
+            .to_string();
+        warn!("# Testing {name}");
+
+        let t = Instant::now();
+        let mut checks = Vec::new();
+        let result = match resolved.resolved_as {
+            ResolvedAs::Flow { .. } => {
+                let mut reports = Vec::new();
+                let res = run_flow_with(
+                    &target,
+                    &resolved,
+                    options,
+                    &mut reports,
+                    |step_resolved, extra| {
+                        let target = target.clone();
+                        async move {
+                            make_request_with(
+                                &target,
+                                &step_resolved,
+                                options,
+                                &extra,
+                                &mut RequestChain::default(),
+                            )
+                            .await
+                        }
+                    },
+                )
+                .await;
+
+                checks.extend(flow_checks(reports));
+                // Failed checks stop the flow, but they are already counted
+                res.map(|_| ())
+            }
+            _ => make_request_with(
+                &target,
+                &resolved,
+                options,
+                &toml::Table::new(),
+                &mut RequestChain::default(),
+            )
+            .await
+            .map(|exchange| checks.extend(exchange.checks)),
+        };
+
+        cases.push(TestCase {
+            name,
+            elapsed: t.elapsed(),
+            checks,
+            error: result.err().map(|err| format!("{err:#}")),
+        });
+    }
+
+    Ok(cases)
+}
+
+fn collect_requests(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            let mut found = find_available_requests(path)?;
+            found.sort();
+            files.extend(found.into_iter().map(|p| path.join(p)));
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    Ok(files)
+}
+
+fn has_checks(
+    target: &str,
+    resolved: &Resolved,
+    options: &[(String, String)],
+) -> Result<bool> {
+    match resolved.resolved_as {
+        ResolvedAs::Flow { .. } => {
+            let flow = Flow::load(resolved.original_path())?;
+            for step in &flow.steps {
+                if step.vars.contains_key("_assert")
+                    || step.vars.contains_key("_snapshot")
+                {
+                    return Ok(true);
+                }
+                let step_path = resolved.root_dir.join(&step.request);
+                let step_resolved = resolve_path(&step_path)?;
+                let scope = load_env(target, &step_resolved, options)?;
+                if is_tested(&scope) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => {
+            let scope = load_env(target, resolved, options)?;
+            Ok(is_tested(&scope))
+        }
+    }
+}
+
+fn is_tested(scope: &Scope) -> bool {
+    scope.assertions().is_some() || scope.snapshot().is_some()
+}
+
+/// The checks of all steps of a flow, named after the step.
+fn flow_checks(reports: Vec<StepReport>) -> Vec<CheckResult> {
+    reports
+        .into_iter()
+        .flat_map(|report| {
+            report.checks.into_iter().map(move |c| CheckResult {
+                description: format!("{}: {}", report.name, c.description),
+                ..c
+            })
+        })
+        .collect()
+}
+
+pub fn print_summary(cases: &[TestCase]) {
+    let checks: usize = cases.iter().map(|c| c.checks.len()).sum();
+    let failures: usize = cases.iter().map(TestCase::failures).sum();
+
+    for case in cases.iter().filter(|c| c.failures() > 0) {
+        warn!("# FAILED: {}", case.name);
+        if let Some(err) = &case.error {
+            warn!("#   {err}");
+        }
+        for check in case.checks.iter().filter(|c| !c.passed()) {
+            warn!(
+                "#   {} ({})",
+                check.description,
+                check.failure.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    warn!(
+        "# {} requests tested, {checks} checks, {failures} failures",
+        cases.len()
+    );
+}
+
+pub fn write_junit(path: &Path, cases: &[TestCase]) -> Result<()> {
+    let tests: usize = cases.iter().map(TestCase::tests).sum();
+    let failures: usize = cases.iter().map(TestCase::failures).sum();
+
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites name="hitman" tests="{tests}" failures="{failures}">"#
+    )?;
+
+    for case in cases {
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" time="{:.3}">"#,
+            xml_escape(&case.name),
+            case.tests(),
+            case.failures(),
+            case.elapsed.as_secs_f64()
+        )?;
+
+        if let Some(err) = &case.error {
+            writeln!(
+                out,
+                r#"    <testcase classname="{0}" name="{0}"><error message="{1}"/></testcase>"#,
+                xml_escape(&case.name),
+                xml_escape(err)
+            )?;
+        }
+
+        for check in &case.checks {
+            let name = xml_escape(&check.description);
+            let classname = xml_escape(&case.name);
+            match &check.failure {
+                None => writeln!(
+                    out,
+                    r#"    <testcase classname="{classname}" name="{name}"/>"#
+                )?,
+                Some(failure) => writeln!(
+                    out,
+                    r#"    <testcase classname="{classname}" name="{name}"><failure message="{}"/></testcase>"#,
+                    xml_escape(failure)
+                )?,
+            }
+        }
+
+        writeln!(out, "  </testsuite>")?;
+    }
+
+    writeln!(out, "</testsuites>")?;
+
+    fs::write(path, out)?;
+
+    Ok(())
+}
+
+pub fn write_tap(path: &Path, cases: &[TestCase]) -> Result<()> {
+    let mut points = Vec::new();
+    for case in cases {
+        if let Some(err) = &case.error {
+            points.push((format!("{}: request", case.name), Some(err.clone())));
+        }
+        for check in &case.checks {
+            points.push((
+                format!("{}: {}", case.name, check.description),
+                check.failure.clone(),
+            ));
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "TAP version 13")?;
+    writeln!(out, "1..{}", points.len())?;
+    for (i, (description, failure)) in points.iter().enumerate() {
+        match failure {
+            None => writeln!(out, "ok {} - {description}", i + 1)?,
+            Some(failure) => {
+                writeln!(out, "not ok {} - {description}", i + 1)?;
+                writeln!(out, "  ---")?;
+                writeln!(out, "  message: {failure:?}")?;
+                writeln!(out, "  ...")?;
+            }
+        }
+    }
+
+    fs::write(path, out)?;
+
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use mktemp::Temp;
+    use reqwest::{header::HeaderMap, StatusCode};
+
+    use crate::{
+        flow::FlowOutcome,
+        redact::Redactor,
+        request::{Exchange, HitmanResponse},
+    };
+
+    use super::*;
+
+    fn cases() -> Vec<TestCase> {
+        vec![TestCase {
+            name: "login.http".into(),
+            elapsed: Duration::from_millis(20),
+            checks: vec![
+                CheckResult {
+                    description: "status is 200".into(),
+                    failure: None,
+                },
+                CheckResult {
+                    description: "$.token exists".into(),
+                    failure: Some("not found".into()),
+                },
+            ],
+            error: None,
+        }]
+    }
+
+    #[test]
+    fn writes_tap() {
+        let tmp = Temp::new_file().unwrap();
+        write_tap(&tmp, &cases()).unwrap();
+
+        let tap = fs::read_to_string(&tmp).unwrap();
+        assert_eq!(
+            tap,
+            "TAP version 13\n\
+             1..2\n\
+             ok 1 - login.http: status is 200\n\
+             not ok 2 - login.http: $.token exists\n  \
+             ---\n  \
+             message: \"not found\"\n  \
+             ...\n"
+        );
+    }
+
+    #[test]
+    fn writes_junit() {
+        let tmp = Temp::new_file().unwrap();
+        write_junit(&tmp, &cases()).unwrap();
+
+        let xml = fs::read_to_string(&tmp).unwrap();
+        assert!(xml
+            .contains(r#"<testsuites name="hitman" tests="2" failures="1">"#));
+        assert!(xml.contains(
+            r#"<testcase classname="login.http" name="$.token exists"><failure message="not found"/></testcase>"#
+        ));
+    }
+
+    #[test]
+    fn counts_errors_as_junit_tests() {
+        let mut cases = cases();
+        cases[0].error = Some("connection refused".into());
+        cases.push(TestCase {
+            name: "logout.http".into(),
+            elapsed: Duration::from_millis(5),
+            checks: Vec::new(),
+            error: Some("timeout".into()),
+        });
+
+        let tmp = Temp::new_file().unwrap();
+        write_junit(&tmp, &cases).unwrap();
+
+        let xml = fs::read_to_string(&tmp).unwrap();
+        assert!(xml
+            .contains(r#"<testsuites name="hitman" tests="4" failures="3">"#));
+        assert!(xml.contains(r#"<testsuite name="login.http" tests="3""#));
+        assert!(xml.contains(r#"<testsuite name="logout.http" tests="1""#));
+        assert_eq!(xml.matches("<testcase ").count(), 4);
+    }
+
+    #[test]
+    fn counts_failed_flow_checks_once() {
+        let tmp = Temp::new_dir().unwrap();
+        fs::write(tmp.join("hitman.toml"), "[default]\n").unwrap();
+        for name in ["login.http", "logout.http"] {
+            fs::write(tmp.join(name), "GET /").unwrap();
+        }
+        fs::write(
+            tmp.join("session.flow.toml"),
+            "[[step]]\nrequest = 'login.http'\n\n\
+            [[step]]\nrequest = 'logout.http'\n",
+        )
+        .unwrap();
+        let resolved = resolve_path(&tmp.join("session.flow.toml")).unwrap();
+
+        let mut reports = Vec::new();
+        let res = block_on(run_flow_with(
+            "default",
+            &resolved,
+            &[],
+            &mut reports,
+            |_, _| async {
+                Ok(Exchange {
+                    response: HitmanResponse {
+                        status: StatusCode::OK,
+                        headers: HeaderMap::new(),
+                        body: String::new(),
+                        elapsed: Duration::ZERO,
+                    },
+                    extracted: toml::Table::new(),
+                    checks: cases().remove(0).checks,
+                    snapshot: None,
+                    redactor: Redactor::default(),
+                })
+            },
+        ));
+
+        assert_eq!(res.unwrap(), FlowOutcome::ChecksFailed { step: 1 });
+
+        let case = TestCase {
+            name: "session.flow.toml".into(),
+            elapsed: Duration::ZERO,
+            checks: flow_checks(reports),
+            error: None,
+        };
+        assert_eq!(case.tests(), 2);
+        assert_eq!(case.failures(), 1);
+        assert_eq!(case.checks[1].description, "login.http: $.token exists");
+    }
+}
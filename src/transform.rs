@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use toml::{Table, Value};
+
+use crate::{
+    extract::json_to_toml,
+    substitute::{literal, substitute, Substitution, SubstitutionValue},
+};
+
+/// A step in the chain of transforms of an extraction rule, like
+/// `jwt.claim:sub`, `base64`, `split:/:2` or `format:Bearer {{value}}`.
+#[derive(Debug)]
+pub enum Transform {
+    Base64,
+    Base64Url,
+    UrlDecode,
+    JwtClaim(String),
+    Split { separator: String, index: i64 },
+    Regex(Regex),
+    Lowercase,
+    Format(String),
+}
+
+impl Transform {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg)),
+            None => (s.trim(), None),
+        };
+
+        Ok(match (name, arg) {
+            ("base64", None) => Self::Base64,
+            ("base64url", None) => Self::Base64Url,
+            ("urldecode", None) => Self::UrlDecode,
+            ("lowercase", None) => Self::Lowercase,
+            ("jwt.claim", Some(claim)) => Self::JwtClaim(claim.trim().into()),
+            ("split", Some(arg)) => {
+                let (separator, index) = arg
+                    .rsplit_once(':')
+                    .ok_or_else(|| anyhow!("Invalid transform: {s}"))?;
+                Self::Split {
+                    separator: separator.to_string(),
+                    index: index
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid transform: {s}"))?,
+                }
+            }
+            ("regex", Some(pattern)) => Self::Regex(Regex::new(pattern)?),
+            ("format", Some(template)) => Self::Format(template.to_string()),
+            _ => bail!("Invalid transform: {s}"),
+        })
+    }
+
+    /// Parse a single transform, or a list of them.
+    pub fn parse_chain(value: &Value) -> Result<Vec<Self>> {
+        match value {
+            Value::String(s) => Ok(vec![Self::parse(s)?]),
+            Value::Array(arr) => arr
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Self::parse(s),
+                    other => bail!("Invalid transform: {other}"),
+                })
+                .collect(),
+            other => bail!("Invalid transform: {other}"),
+        }
+    }
+
+    /// Whether the transform refers to other extracted values.
+    pub fn is_format(&self) -> bool {
+        matches!(self, Self::Format(_))
+    }
+
+    /// Apply the transform to a value. Values that are already extracted
+    /// can be referred to in a format template, and the current value is
+    /// available as `{{value}}`.
+    ///
+    /// Returns `None` if the transform has no result, like a missing claim.
+    pub fn apply(
+        &self,
+        value: &Value,
+        extracted: &Table,
+    ) -> Result<Option<Value>> {
+        let s = || as_string(value);
+        Ok(match self {
+            Self::Base64 => Some(decode(&STANDARD_NO_PAD, &s()?)?),
+            Self::Base64Url => Some(decode(&URL_SAFE_NO_PAD, &s()?)?),
+            Self::UrlDecode => Some(Value::from(
+                percent_decode_str(&s()?).decode_utf8()?.as_ref(),
+            )),
+            Self::JwtClaim(claim) => jwt_claim(&s()?, claim)?,
+            Self::Split { separator, index } => {
+                let s = s()?;
+                let parts: Vec<_> = s.split(separator.as_str()).collect();
+                let pos = if *index < 0 {
+                    usize::try_from(-index)
+                        .ok()
+                        .and_then(|i| parts.len().checked_sub(i))
+                } else {
+                    usize::try_from(*index).ok()
+                };
+                pos.and_then(|i| parts.get(i)).map(|p| Value::from(*p))
+            }
+            Self::Regex(regex) => regex
+                .captures(&s()?)
+                .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                .map(|m| Value::from(m.as_str())),
+            Self::Lowercase => Some(Value::from(s()?.to_lowercase())),
+            Self::Format(template) => Some(format(template, value, extracted)?),
+        })
+    }
+}
+
+/// Apply a chain of transforms, stopping if one of them has no result.
+pub fn apply_chain(
+    transforms: &[Transform],
+    value: Value,
+    extracted: &Table,
+) -> Result<Option<Value>> {
+    let mut value = value;
+    for transform in transforms {
+        match transform.apply(&value, extracted)? {
+            Some(v) => value = v,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(value))
+}
+
+fn as_string(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => {
+            Ok(value.to_string())
+        }
+        other => bail!("Can't transform {} value", other.type_str()),
+    }
+}
+
+fn decode(engine: &impl Engine, s: &str) -> Result<Value> {
+    let bytes = engine
+        .decode(s.trim().trim_end_matches('='))
+        .context("Invalid base64")?;
+
+    Ok(Value::from(
+        String::from_utf8(bytes).context("Decoded value is not text")?,
+    ))
+}
+
+/// A claim from the payload of a JWT. The signature is not verified.
+fn jwt_claim(token: &str, claim: &str) -> Result<Option<Value>> {
+    let token = token.trim();
+    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+
+    let Some(payload) = token.split('.').nth(1) else {
+        bail!("Invalid JWT");
+    };
+    let bytes = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("Invalid JWT")?;
+    let json: JsonValue =
+        serde_json::from_slice(&bytes).context("Invalid JWT")?;
+
+    Ok(json.get(claim).map(json_to_toml))
+}
+
+fn format(template: &str, value: &Value, extracted: &Table) -> Result<Value> {
+    let mut vars = HashMap::new();
+    for (key, v) in extracted.iter().chain([(&"value".into(), value)]) {
+        if let Ok(s) = as_string(v) {
+            vars.insert(key.clone(), SubstitutionValue::Single(literal(&s)));
+        }
+    }
+
+    match substitute(template, &vars)? {
+        Substitution::Complete(s) => {
+            Ok(Value::from(s.strip_suffix('\n').unwrap_or(&s)))
+        }
+        Substitution::ValueMissing { key, .. } => {
+            bail!("Value not extracted: {key}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(rules: &[&str], value: &str) -> Option<Value> {
+        let transforms: Vec<_> =
+            rules.iter().map(|s| Transform::parse(s).unwrap()).collect();
+        let extracted = toml::from_str("token_type = 'Bearer'").unwrap();
+
+        apply_chain(&transforms, Value::from(value), &extracted).unwrap()
+    }
+
+    #[test]
+    fn reads_jwt_claims() {
+        // {"sub":"Apple-42","admin":true}
+        let token = "eyJhbGciOiJIUzI1NiJ9.\
+            eyJzdWIiOiJBcHBsZS00MiIsImFkbWluIjp0cnVlfQ.c2ln";
+
+        assert_eq!(
+            apply(&["jwt.claim:sub", "lowercase"], token),
+            Some(Value::from("apple-42"))
+        );
+        assert_eq!(
+            apply(&["jwt.claim:admin"], token),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(apply(&["jwt.claim:missing"], token), None);
+    }
+
+    #[test]
+    fn decodes_and_slices_strings() {
+        assert_eq!(
+            apply(&["base64"], "YXBwbGU6cGVhcg=="),
+            Some(Value::from("apple:pear"))
+        );
+        assert_eq!(
+            apply(&["urldecode", "split:/:-1"], "%2Fapples%2F42"),
+            Some(Value::from("42"))
+        );
+        assert_eq!(
+            apply(&["regex:id=(\\d+)"], "/apple?id=42"),
+            Some(Value::from("42"))
+        );
+        assert_eq!(apply(&["split:,:3"], "a,b"), None);
+        assert!(Transform::parse("split:,").is_err());
+    }
+
+    #[test]
+    fn formats_with_extracted_values() {
+        assert_eq!(
+            apply(&["format:{{token_type}} {{value}}"], "abc"),
+            Some(Value::from("Bearer abc"))
+        );
+    }
+}
@@ -9,7 +9,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use crossterm::event::{self, Event, KeyEventKind};
 use ratatui::{
     backend::Backend,
@@ -18,20 +18,33 @@ use ratatui::{
     widgets::Paragraph,
     Frame, Terminal,
 };
+use reqwest::header::HeaderName;
 use tokio::task::JoinHandle;
-use toml::Value;
+use toml::{Table as TomlTable, Value};
 
 use hitman::{
+    assertion::check_response,
+    compare::{compare_targets, Comparison},
     env::{
-        find_available_requests, find_environments, get_target, load_env,
-        set_target, update_data,
+        find_available_requests, find_targets, get_target, load_env,
+        set_target, update_data, Target,
     },
-    extract::extract_variables,
-    request::{build_client, do_request, HitmanRequest},
-    resolve::{find_root_dir, resolve_path, Resolved},
-    scope::Replacement,
+    extract::{extract_from_history, extract_variables},
+    flow::{run_flow_with, transcript, FlowOutcome},
+    history::{list_entries, record, HistoryEntry},
+    prompt::{
+        lookup, prepare_request_in_scope, prepare_request_interactive,
+        NoUserInteraction,
+    },
+    redact::Redactor,
+    request::{
+        build_client, do_request, Exchange, HitmanRequest, HitmanResponse,
+    },
+    resolve::{find_root_dir, resolve_path, Resolved, ResolvedAs},
+    scope::{Replacement, Scope},
+    snapshot::{verify_snapshot, Snapshot},
     substitute::{
-        prepare_request,
+        default_headers, literal,
         Substitution::{Complete, ValueMissing},
         SubstitutionValue,
     },
@@ -70,6 +83,20 @@ pub struct App {
     error: Option<String>,
     should_quit: bool,
     vsplit: u16,
+
+    /// Requests waiting for a required request to complete
+    pending_requests: Vec<PendingRequest>,
+    /// Keys that required requests have been run for
+    requested_keys: Vec<String>,
+
+    /// Snapshot of the last response, that can be accepted
+    last_snapshot: Option<Snapshot>,
+    notice: Option<String>,
+}
+
+pub struct PendingRequest {
+    file_path: String,
+    vars: HashMap<String, SubstitutionValue<String>>,
 }
 
 pub enum AppState {
@@ -89,13 +116,42 @@ pub enum AppState {
     },
 
     RunningRequest {
-        handle: JoinHandle<HttpRequestInfo>,
+        handle: JoinHandle<(HttpRequestInfo, Option<Snapshot>)>,
+        progress: Progress,
+    },
+
+    RunningFlow {
+        handle: JoinHandle<String>,
         progress: Progress,
     },
 
     SelectTarget {
-        component: Select<String>,
+        component: Select<Target>,
+        /// Select a target to compare the current target with
+        compare: bool,
+    },
+
+    RunningComparison {
+        handle: JoinHandle<Result<Comparison>>,
+        progress: Progress,
     },
+
+    History {
+        component: Select<HistoryItem>,
+    },
+
+    /// Values extracted from a stored response, waiting to be written to
+    /// the data file
+    ConfirmExtraction {
+        extracted: TomlTable,
+    },
+}
+
+/// A stored response, in the history pane.
+#[derive(Clone, PartialEq)]
+pub struct HistoryItem {
+    path: PathBuf,
+    summary: String,
 }
 
 pub enum Intent {
@@ -118,9 +174,19 @@ pub enum Intent {
         resolved: Resolved,
         prepared_request: HitmanRequest,
     },
+    RunFlow(Resolved),
     ShowResult(HttpRequestInfo),
+    ShowTranscript(String),
+    AcceptSnapshot,
     SelectTarget,
     AcceptSelectTarget(String),
+    SelectCompareTarget,
+    CompareWith(String),
+    ShowComparison(Comparison),
+    ShowHistory,
+    PreviewHistory(PathBuf),
+    PreviewExtraction,
+    ApplyExtraction,
     EditRequest,
     NewRequest,
     AcceptNewRequest(String),
@@ -148,6 +214,10 @@ impl App {
             error: None,
             should_quit: false,
             vsplit: 60,
+            pending_requests: Vec::new(),
+            requested_keys: Vec::new(),
+            last_snapshot: None,
+            notice: None,
         };
 
         app.populate_requests()?;
@@ -194,6 +264,7 @@ impl App {
                 self.should_quit = true;
             }
             Abort => {
+                self.clear_pending_requests();
                 self.set_state(AppState::Idle);
             }
             ShowHelp => {
@@ -215,11 +286,17 @@ impl App {
                 resolved,
                 prepared_request,
             } => {
-                let req = HttpRequestMessage(prepared_request.clone());
+                let req = HttpRequestMessage(
+                    redactor(&self.target, &resolved)
+                        .request(&prepared_request),
+                );
                 let info = HttpRequestInfo::new(req, RequestStatus::Running);
                 self.output_view.show_request(info);
                 self.send_request(resolved, prepared_request);
             }
+            RunFlow(resolved) => {
+                self.run_flow(resolved);
+            }
             AskForValue {
                 key,
                 file_path,
@@ -235,21 +312,150 @@ impl App {
                 });
             }
             ShowResult(info) => {
+                let complete = info.is_complete();
                 self.output_view.show_request(info);
                 self.set_state(AppState::Idle);
+
+                if let Some(PendingRequest { file_path, vars }) =
+                    self.pending_requests.pop().filter(|_| complete)
+                {
+                    return Ok(Some(PrepareRequest { file_path, vars }));
+                }
+                self.clear_pending_requests();
+            }
+            ShowTranscript(text) => {
+                self.output_view.show_transcript(text);
+                self.set_state(AppState::Idle);
+            }
+            AcceptSnapshot => {
+                let snapshot = self
+                    .last_snapshot
+                    .take()
+                    .context("No response to accept as snapshot")?;
+                snapshot.save()?;
+                self.set_state(AppState::Idle);
+                self.notice = Some(format!(
+                    "Snapshot saved to {}",
+                    snapshot.path.display()
+                ));
             }
             SelectTarget => {
-                let envs = find_environments(&self.root_dir)?;
+                let envs = find_targets(&self.root_dir)?;
                 let component =
                     Select::new("Select target".into(), "target".into(), envs);
 
-                self.set_state(AppState::SelectTarget { component });
+                self.set_state(AppState::SelectTarget {
+                    component,
+                    compare: false,
+                });
             }
             AcceptSelectTarget(s) => {
                 set_target(&self.root_dir, &s)?;
                 self.target = s;
                 self.set_state(AppState::Idle);
             }
+            SelectCompareTarget => {
+                let envs = find_targets(&self.root_dir)?;
+                let component = Select::new(
+                    format!("Compare {} with", self.target),
+                    "target".into(),
+                    envs,
+                );
+
+                self.set_state(AppState::SelectTarget {
+                    component,
+                    compare: true,
+                });
+            }
+            CompareWith(other) => {
+                let file_path = self
+                    .request_selector
+                    .selector
+                    .selected_item()
+                    .context("No request selected")?;
+                let resolved = resolve_path(Path::new(file_path))?;
+                self.compare_with(resolved, other);
+            }
+            ShowHistory => {
+                let file_path = self
+                    .request_selector
+                    .selector
+                    .selected_item()
+                    .context("No request selected")?;
+                let resolved = resolve_path(Path::new(file_path))?;
+
+                let items = list_entries(&self.root_dir, Some(&resolved))
+                    .into_iter()
+                    .map(|path| {
+                        let entry = HistoryEntry::load(&path)?;
+                        Ok(HistoryItem {
+                            path,
+                            summary: entry.summary(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                let Some(first) = items.first().map(|i| i.path.clone()) else {
+                    bail!("No history for {file_path}");
+                };
+
+                let component =
+                    Select::new("History".into(), "Search".into(), items);
+                self.set_state(AppState::History { component });
+
+                return Ok(Some(PreviewHistory(first)));
+            }
+            PreviewHistory(path) => {
+                let entry = HistoryEntry::load(&path)?;
+                // The entry is already redacted, unless secrets were shown
+                let exchange = Exchange {
+                    response: entry.response()?,
+                    extracted: TomlTable::new(),
+                    checks: Vec::new(),
+                    snapshot: None,
+                    redactor: Redactor::default(),
+                };
+                let (response, elapsed) = format_response(&exchange)?;
+
+                let request = HttpRequestMessage(entry.request()?);
+                self.output_view.show_request(HttpRequestInfo::new(
+                    request,
+                    RequestStatus::Complete { response, elapsed },
+                ));
+            }
+            PreviewExtraction => {
+                let file_path = self
+                    .request_selector
+                    .selector
+                    .selected_item()
+                    .context("No request selected")?;
+                let resolved = resolve_path(Path::new(file_path))?;
+                let extracted = extract_from_history(&self.target, &resolved)?;
+
+                self.output_view
+                    .show_transcript(toml::to_string(&extracted)?);
+                self.set_state(AppState::ConfirmExtraction { extracted });
+                self.notice = Some(
+                    "Press <Enter> to update the data file, <Esc> to cancel"
+                        .into(),
+                );
+            }
+            ApplyExtraction => {
+                let AppState::ConfirmExtraction { extracted } = &self.state
+                else {
+                    bail!("No extracted values to apply");
+                };
+                update_data(&self.root_dir, extracted)?;
+
+                let count = extracted.len();
+                self.set_state(AppState::Idle);
+                self.notice =
+                    Some(format!("Updated {count} values in the data file"));
+            }
+            ShowComparison(comparison) => {
+                self.output_view.show_comparison(comparison);
+                self.set_state(AppState::Idle);
+            }
             EditRequest => {
                 let selected_item =
                     self.request_selector.selector.selected_item();
@@ -268,6 +474,7 @@ impl App {
                 return Ok(Some(Update(Some(file_path))));
             }
             ShowError(err) => {
+                self.clear_pending_requests();
                 self.error = Some(err);
                 self.state = AppState::Idle;
             }
@@ -278,9 +485,15 @@ impl App {
 
     fn set_state(&mut self, state: AppState) {
         self.error = None;
+        self.notice = None;
         self.state = state;
     }
 
+    fn clear_pending_requests(&mut self) {
+        self.pending_requests.clear();
+        self.requested_keys.clear();
+    }
+
     fn populate_requests(&mut self) -> Result<()> {
         let reqs = find_available_requests(&self.root_dir)?;
         let reqs: Vec<String> = reqs
@@ -296,7 +509,9 @@ impl App {
     async fn process_events(&mut self) -> Result<Option<Intent>> {
         // Don't waste so much CPU when idle
         let poll_timeout = match self.state {
-            AppState::RunningRequest { .. } => Duration::from_millis(50),
+            AppState::RunningRequest { .. }
+            | AppState::RunningFlow { .. }
+            | AppState::RunningComparison { .. } => Duration::from_millis(50),
             _ => Duration::from_secs(1),
         };
 
@@ -309,7 +524,29 @@ impl App {
         if let AppState::RunningRequest { handle, .. } = &mut self.state {
             if handle.is_finished() {
                 return Ok(match handle.await {
-                    Ok(res) => Some(Intent::ShowResult(res)),
+                    Ok((res, snapshot)) => {
+                        self.last_snapshot = snapshot;
+                        Some(Intent::ShowResult(res))
+                    }
+                    Err(err) => Some(Intent::ShowError(err.to_string())),
+                });
+            }
+        }
+
+        if let AppState::RunningFlow { handle, .. } = &mut self.state {
+            if handle.is_finished() {
+                return Ok(match handle.await {
+                    Ok(res) => Some(Intent::ShowTranscript(res)),
+                    Err(err) => Some(Intent::ShowError(err.to_string())),
+                });
+            }
+        }
+
+        if let AppState::RunningComparison { handle, .. } = &mut self.state {
+            if handle.is_finished() {
+                return Ok(match handle.await {
+                    Ok(Ok(res)) => Some(Intent::ShowComparison(res)),
+                    Ok(Err(err)) => Some(Intent::ShowError(format!("{err:#}"))),
                     Err(err) => Some(Intent::ShowError(err.to_string())),
                 });
             }
@@ -319,7 +556,7 @@ impl App {
     }
 
     fn try_request(
-        &self,
+        &mut self,
         file_path: String,
         mut vars: HashMap<String, SubstitutionValue<String>>,
     ) -> Result<Option<Intent>> {
@@ -328,7 +565,13 @@ impl App {
         let path = PathBuf::from(file_path.clone());
 
         let resolved = resolve_path(&path)?;
-        let intent = match prepare_request(&resolved, &vars)? {
+        if let ResolvedAs::Flow { .. } = resolved.resolved_as {
+            return Ok(Some(Intent::RunFlow(resolved)));
+        }
+
+        let scope = load_env(&self.target, &resolved, &[])?;
+
+        let intent = match prepare_request_in_scope(&resolved, &scope, &vars)? {
             Complete(prepared_request) => Some(Intent::SendRequest {
                 resolved,
                 prepared_request,
@@ -338,13 +581,20 @@ impl App {
                 fallback,
                 multiple,
             } => {
-                let scope = load_env(&self.target, &resolved, &[])?;
+                let (looked_up, replacement) = lookup(&scope, &vars, &key)?;
+                let fallback = fallback.filter(|_| looked_up == key);
+                let key = looked_up;
 
-                match scope.lookup(&key)? {
+                match replacement {
                     Replacement::Value(value) => {
                         vars.insert(key, SubstitutionValue::Single(value));
                         Some(Intent::PrepareRequest { file_path, vars })
                     }
+                    Replacement::Literal(value) => {
+                        let value = SubstitutionValue::Single(literal(&value));
+                        vars.insert(key, value);
+                        Some(Intent::PrepareRequest { file_path, vars })
+                    }
                     Replacement::MultipleValuesFound { key, values } => {
                         Some(Intent::AskForValue {
                             key,
@@ -364,6 +614,9 @@ impl App {
                             params: AskForValueParams::Prompt { fallback },
                         })
                     }
+                    Replacement::RequiresRequest { key, path } => {
+                        Some(self.require_request(file_path, vars, key, &path)?)
+                    }
                 }
             }
         };
@@ -371,6 +624,40 @@ impl App {
         Ok(intent)
     }
 
+    /// Run the request that provides `key` first, and then continue
+    /// preparing the current request.
+    fn require_request(
+        &mut self,
+        file_path: String,
+        vars: HashMap<String, SubstitutionValue<String>>,
+        key: String,
+        path: &str,
+    ) -> Result<Intent> {
+        let required = self.root_dir.join(path);
+
+        let chain: Vec<&str> = self
+            .pending_requests
+            .iter()
+            .map(|p| p.file_path.as_str())
+            .chain([file_path.as_str()])
+            .collect();
+        if chain.iter().any(|p| is_same_file(Path::new(p), &required)) {
+            bail!("Cyclic requirement: {} -> {path}", chain.join(" -> "));
+        }
+        if self.requested_keys.contains(&key) {
+            bail!("{path} did not provide a value for '{key}'");
+        }
+
+        self.requested_keys.push(key);
+        self.pending_requests
+            .push(PendingRequest { file_path, vars });
+
+        Ok(Intent::PrepareRequest {
+            file_path: required.display().to_string(),
+            vars: HashMap::new(),
+        })
+    }
+
     fn preview_request(&mut self, file_path: Option<String>) -> Result<()> {
         if let Some(file_path) = file_path {
             let path = PathBuf::from(file_path.clone());
@@ -378,7 +665,10 @@ impl App {
             // TODO: Preview separate from output?
             // TODO: Highlight substitutions and current values
 
-            let f = read_to_string(path)?;
+            let mut f = read_to_string(&path)?;
+            if let Ok(defaults) = self.preview_default_headers(&path) {
+                f = with_default_headers(&f, &defaults);
+            }
 
             self.request_selector.try_select(&file_path);
 
@@ -390,6 +680,21 @@ impl App {
         Ok(())
     }
 
+    /// The default headers for a plain HTTP request, that are added when
+    /// it's sent.
+    fn preview_default_headers(
+        &self,
+        path: &Path,
+    ) -> Result<Vec<(HeaderName, String)>> {
+        let resolved = resolve_path(path)?;
+        if !matches!(resolved.resolved_as, ResolvedAs::Simple { .. }) {
+            return Ok(Vec::new());
+        }
+
+        let scope = load_env(&self.target, &resolved, &[])?;
+        default_headers(&scope)
+    }
+
     fn send_request(
         &mut self,
         resolved: Resolved,
@@ -407,6 +712,38 @@ impl App {
         self.set_state(state);
     }
 
+    fn run_flow(&mut self, resolved: Resolved) {
+        let target = self.target.clone();
+        let handle =
+            tokio::spawn(async move { run_flow(target, &resolved).await });
+
+        let state = AppState::RunningFlow {
+            handle,
+            progress: Progress,
+        };
+        self.set_state(state);
+    }
+
+    fn compare_with(&mut self, resolved: Resolved, other: String) {
+        let target = self.target.clone();
+        let handle = tokio::spawn(async move {
+            compare_targets(
+                &resolved,
+                [&target, &other],
+                &[],
+                &[],
+                &NoUserInteraction,
+            )
+            .await
+        });
+
+        let state = AppState::RunningComparison {
+            handle,
+            progress: Progress,
+        };
+        self.set_state(state);
+    }
+
     fn handle_global_key(&mut self, event: &Event) -> Option<Intent> {
         match mapkey(event) {
             KeyMapping::Editor => return Some(Intent::EditRequest),
@@ -416,10 +753,22 @@ impl App {
                 return Some(Intent::Update(selected_item.cloned()));
             }
             KeyMapping::New => return Some(Intent::NewRequest),
+            KeyMapping::AcceptSnapshot => {
+                return Some(Intent::AcceptSnapshot);
+            }
             KeyMapping::Abort => return Some(Intent::Quit),
             KeyMapping::SelectTarget => {
                 return Some(Intent::SelectTarget);
             }
+            KeyMapping::Compare => {
+                return Some(Intent::SelectCompareTarget);
+            }
+            KeyMapping::History => {
+                return Some(Intent::ShowHistory);
+            }
+            KeyMapping::Extract => {
+                return Some(Intent::PreviewExtraction);
+            }
             KeyMapping::IncreaseWidth => {
                 self.vsplit += 5;
             }
@@ -469,6 +818,13 @@ fn create_prompt_component(
     }
 }
 
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 fn open_in_editor<S>(
     file_path: &String,
     screen: &mut S,
@@ -555,6 +911,41 @@ impl InteractiveComponent for App {
                         }
                     }
 
+                    AppState::RunningFlow { handle, .. } => {
+                        if let KeyMapping::Abort = mapkey(event) {
+                            handle.abort();
+                            return Some(Abort);
+                        }
+                    }
+
+                    AppState::History { component } => {
+                        if let Some(intent) = component.handle_event(event) {
+                            match intent {
+                                SelectIntent::Change(Some(item)) => {
+                                    return Some(PreviewHistory(item.path));
+                                }
+                                SelectIntent::Change(None) => (),
+                                SelectIntent::Abort
+                                | SelectIntent::Accept(_) => {
+                                    return Some(Abort);
+                                }
+                            }
+                        }
+                    }
+
+                    AppState::RunningComparison { handle, .. } => {
+                        if let KeyMapping::Abort = mapkey(event) {
+                            handle.abort();
+                            return Some(Abort);
+                        }
+                    }
+
+                    AppState::ConfirmExtraction { .. } => match mapkey(event) {
+                        KeyMapping::Accept => return Some(ApplyExtraction),
+                        KeyMapping::Abort => return Some(Abort),
+                        _ => (),
+                    },
+
                     AppState::NewRequestPrompt { prompt } => {
                         if let Some(intent) = prompt.handle_prompt(event) {
                             match intent {
@@ -573,7 +964,7 @@ impl InteractiveComponent for App {
                         }
                     }
 
-                    AppState::SelectTarget { component } => {
+                    AppState::SelectTarget { component, compare } => {
                         if let Some(intent) = component.handle_event(event) {
                             match intent {
                                 SelectIntent::Abort => {
@@ -581,8 +972,12 @@ impl InteractiveComponent for App {
                                 }
                                 SelectIntent::Accept(s) => {
                                     match s {
-                                        SubstitutionValue::Single(s) => {
-                                            return Some(AcceptSelectTarget(s))
+                                        SubstitutionValue::Single(t) => {
+                                            return Some(if *compare {
+                                                CompareWith(t.name)
+                                            } else {
+                                                AcceptSelectTarget(t.name)
+                                            });
                                         }
                                         SubstitutionValue::Multiple(_) => {
                                             unreachable!(
@@ -656,7 +1051,12 @@ impl App {
     }
 
     fn render_left(&mut self, frame: &mut Frame, area: Rect) {
-        self.request_selector.render_ui(frame, area);
+        match &mut self.state {
+            AppState::History { component } => {
+                component.render_ui(frame, area);
+            }
+            _ => self.request_selector.render_ui(frame, area),
+        }
     }
 
     fn render_status(&self, frame: &mut Frame, area: Rect) {
@@ -680,15 +1080,14 @@ impl App {
         );
 
         // FIXME: <Ctrl+?> opens key mapping window
-        let status_line = self.error.as_ref().map_or_else(
-            || {
-                Paragraph::new(
-                    "Ctrl+S: Select target, ? - Show keyboard shortcuts",
-                )
-                .dark_gray()
-            },
-            |msg| Paragraph::new(msg.clone()).red().reversed(),
-        );
+        let status_line = match (&self.error, &self.notice) {
+            (Some(msg), _) => Paragraph::new(msg.clone()).red().reversed(),
+            (None, Some(msg)) => Paragraph::new(msg.clone()).green(),
+            (None, None) => Paragraph::new(
+                "Ctrl+S: Select target, ? - Show keyboard shortcuts",
+            )
+            .dark_gray(),
+        };
 
         frame.render_widget(status_line, layout[2]);
     }
@@ -712,12 +1111,14 @@ impl App {
                 prompt.render_ui(frame, inner_area);
             }
 
-            AppState::SelectTarget { component } => {
+            AppState::SelectTarget { component, .. } => {
                 let inner_area = centered(area, 30, 20);
                 component.render_ui(frame, inner_area);
             }
 
-            AppState::RunningRequest { progress, .. } => {
+            AppState::RunningRequest { progress, .. }
+            | AppState::RunningFlow { progress, .. }
+            | AppState::RunningComparison { progress, .. } => {
                 progress.render_ui(frame, frame.area());
             }
 
@@ -726,56 +1127,212 @@ impl App {
     }
 }
 
+/// Make the request, and also return a snapshot of the response, that can
+/// be accepted by the user.
 async fn make_request(
     req: HitmanRequest,
     target: String,
     resolved: &Resolved,
-) -> HttpRequestInfo {
-    let request = HttpRequestMessage(req.clone());
-    let status = match do_make_request(req, target, resolved).await {
-        Ok((response, elapsed)) => {
-            RequestStatus::Complete { response, elapsed }
-        }
-        Err(err) => RequestStatus::Failed {
-            error: err.to_string(),
-        },
+) -> (HttpRequestInfo, Option<Snapshot>) {
+    let redactor = redactor(&target, resolved);
+    let request = HttpRequestMessage(redactor.request(&req));
+    let result = send_and_format(req, &target, resolved).await;
+    let (status, snapshot) = match result {
+        Ok((response, elapsed, snapshot)) => (
+            RequestStatus::Complete { response, elapsed },
+            Some(snapshot),
+        ),
+        Err(err) => (
+            RequestStatus::Failed {
+                error: err.to_string(),
+            },
+            None,
+        ),
+    };
+
+    (HttpRequestInfo::new(request, status), snapshot)
+}
+
+async fn send_and_format(
+    req: HitmanRequest,
+    target: &str,
+    resolved: &Resolved,
+) -> Result<(HttpMessage, Duration, Snapshot)> {
+    let scope = load_env(target, resolved, &[])?;
+    let exchange = do_make_request(req, target, &scope, resolved).await?;
+    let (response, elapsed) = format_response(&exchange)?;
+
+    let snapshot = match exchange.snapshot {
+        Some(snapshot) => snapshot,
+        None => Snapshot::take(resolved, &exchange.response, &scope)?,
     };
 
-    HttpRequestInfo::new(request, status)
+    Ok((response, elapsed, snapshot))
+}
+
+async fn run_flow(target: String, resolved: &Resolved) -> String {
+    let mut reports = Vec::new();
+    let res = run_flow_with(
+        &target,
+        resolved,
+        &[],
+        &mut reports,
+        |step_resolved, extra| {
+            let target = target.clone();
+            async move {
+                let mut scope = load_env(&target, &step_resolved, &[])?;
+                scope.merge(extra);
+
+                let req = prepare_request_interactive(
+                    &step_resolved,
+                    &scope,
+                    &NoUserInteraction,
+                )?;
+                do_make_request(req, &target, &scope, &step_resolved).await
+            }
+        },
+    )
+    .await;
+
+    let mut lines = transcript(&reports);
+    match res {
+        Ok(FlowOutcome::Completed) => (),
+        Ok(FlowOutcome::ChecksFailed { step }) => {
+            lines.push(String::new());
+            lines.push(format!("Step {step} failed: assertions not satisfied"));
+        }
+        Err(err) => {
+            lines.push(String::new());
+            lines.push(format!("{err:#}"));
+        }
+    }
+
+    lines.join("\n")
 }
 
 // FIXME: DRY request.rs
 async fn do_make_request(
     req: HitmanRequest,
-    target: String,
+    target: &str,
+    scope: &Scope,
     resolved: &Resolved,
-) -> Result<(HttpMessage, Duration)> {
+) -> Result<Exchange> {
     let client = build_client(&resolved.root_dir)?;
-    let options = vec![];
-    let scope = load_env(&target, resolved, &options)?;
 
     let (res, elapsed) = do_request(&client, &req).await?;
+    let response = HitmanResponse::receive(res, elapsed).await?;
+    record(resolved, target, &req, &response, scope)?;
+
+    let extracted = extract_variables(&response, scope)?;
+    update_data(&resolved.root_dir, &extracted)?;
+
+    let mut checks = check_response(&response, scope)?;
+    let snapshot = verify_snapshot(resolved, &response, scope)?;
+    if let Some(snapshot) = &snapshot {
+        checks.extend(snapshot.check());
+    }
+
+    Ok(Exchange {
+        response,
+        extracted,
+        checks,
+        snapshot,
+        redactor: Redactor::from_scope(scope)?,
+    })
+}
+
+/// Add default headers to the preview of a template, after its own headers,
+/// unless it sets them itself.
+fn with_default_headers(
+    template: &str,
+    defaults: &[(HeaderName, String)],
+) -> String {
+    let mut lines: Vec<String> = template.lines().map(String::from).collect();
+    let end = lines
+        .iter()
+        .position(|line| line.trim().is_empty())
+        .unwrap_or(lines.len());
+
+    let own: Vec<String> = lines[..end]
+        .iter()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, _)| name.trim().to_lowercase())
+        .collect();
+
+    let added = defaults
+        .iter()
+        .filter(|(name, _)| !own.iter().any(|n| n == name.as_str()))
+        .map(|(name, value)| format!("{name}: {value}  # default"));
+    lines.splice(end..end, added);
+
+    lines.join("\n")
+}
 
+/// Masks secrets in the output, using the values and `_redact` patterns of
+/// the target. The request fails later, if the scope can't be loaded.
+fn redactor(target: &str, resolved: &Resolved) -> Redactor {
+    load_env(target, resolved, &[])
+        .and_then(|scope| Redactor::from_scope(&scope))
+        .unwrap_or_default()
+}
+
+fn format_response(exchange: &Exchange) -> Result<(HttpMessage, Duration)> {
+    let res = &exchange.redactor.response(&exchange.response);
     let mut response = HttpMessage::default();
     writeln!(
         response.header,
         "< HTTP/1.1 {} {}",
-        res.status().as_u16(),
-        res.status().canonical_reason().unwrap_or("")
+        res.status.as_u16(),
+        res.status.canonical_reason().unwrap_or("")
     )?;
-    for (name, value) in res.headers() {
+    for (name, value) in &res.headers {
         writeln!(response.header, "< {}: {}", name, value.to_str()?)?;
     }
+    for check in &exchange.checks {
+        match &check.failure {
+            None => writeln!(response.header, "# ok: {}", check.description)?,
+            Some(failure) => writeln!(
+                response.header,
+                "# FAILED: {} ({failure})",
+                check.description
+            )?,
+        }
+    }
+    if let Some(snapshot) = &exchange.snapshot {
+        match &snapshot.differences {
+            None => writeln!(
+                response.header,
+                "# Snapshot saved to {}",
+                snapshot.path.display()
+            )?,
+            Some(diffs) => {
+                for diff in diffs {
+                    writeln!(response.header, "# {diff}")?;
+                }
+            }
+        }
+    }
     writeln!(response.header)?;
 
-    if let Ok(json) = res.json::<serde_json::Value>().await {
-        writeln!(response.body, "{}", serde_json::to_string_pretty(&json)?)?;
+    if let Some(body) = res.formatted_body()? {
+        writeln!(response.body, "{body}")?;
+        response.syntax = if res.json().is_some() { "json" } else { "xml" };
+    }
+
+    Ok((response, res.elapsed))
+}
 
-        let vars = extract_variables(&json, &scope)?;
-        update_data(&resolved.root_dir, &vars)?;
+impl SelectItem for Target {
+    fn text(&self) -> String {
+        self.to_string()
     }
+}
 
-    Ok((response, elapsed))
+impl SelectItem for HistoryItem {
+    fn text(&self) -> String {
+        self.summary.clone()
+    }
 }
 
 impl SelectItem for Value {
@@ -13,12 +13,16 @@ pub enum KeyMapping {
     ScrollUp,
     ScrollDown,
     SelectTarget,
+    Compare,
+    History,
     ToggleHelp,
     ToggleWrap,
     ToggleHeaders,
     Reload,
     Editor,
     New,
+    AcceptSnapshot,
+    Extract,
     IncreaseWidth,
     DecreaseWitdh,
 }
@@ -55,9 +59,13 @@ fn mapkey_keypress(key: &KeyEvent) -> KeyMapping {
         (KeyModifiers::CONTROL, Char('u')) => KeyMapping::ScrollUp,
         (KeyModifiers::CONTROL, Char('d')) => KeyMapping::ScrollDown,
         (KeyModifiers::CONTROL, Char('s')) => KeyMapping::SelectTarget,
+        (KeyModifiers::CONTROL, Char('t')) => KeyMapping::Compare,
+        (KeyModifiers::CONTROL, Char('o')) => KeyMapping::History,
         (KeyModifiers::CONTROL, Char('r')) => KeyMapping::Reload,
         (KeyModifiers::CONTROL, Char('e')) => KeyMapping::Editor,
         (KeyModifiers::CONTROL, Char('a')) => KeyMapping::New,
+        (KeyModifiers::CONTROL, Char('y')) => KeyMapping::AcceptSnapshot,
+        (KeyModifiers::CONTROL, Char('x')) => KeyMapping::Extract,
         (KeyModifiers::CONTROL, Char(' ')) => KeyMapping::ToggleHeaders,
         (KeyModifiers::NONE, Char('<')) => KeyMapping::DecreaseWitdh,
         (KeyModifiers::NONE, Char('>')) => KeyMapping::IncreaseWidth,
@@ -76,9 +84,13 @@ pub fn keymap_list() -> Vec<(&'static str, &'static str)> {
         ("<C-u>", "Scroll up"),
         ("<C-d>", "Scroll down"),
         ("<C-s>", "Select target"),
+        ("<C-t>", "Compare with another target"),
+        ("<C-o>", "Browse response history"),
         ("<C-r>", "Re-scan folder"),
         ("<C-e>", "Edit selected request"),
         ("<C-a>", "New request"),
+        ("<C-y>", "Accept response as snapshot"),
+        ("<C-x>", "Re-run extraction on last response"),
         ("<Esc> or <C-c>", "Abort"),
         ("<C-space>", "Toggle request headers"),
         (",", "Toggle output wrapping"),
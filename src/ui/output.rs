@@ -1,9 +1,12 @@
 use std::time::Duration;
 
 use crossterm::event::Event;
-use hitman::request::HitmanRequest;
+use hitman::{
+    compare::{Comparison, Row},
+    request::HitmanRequest,
+};
 use ratatui::{
-    layout::Rect,
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Style, Stylize},
     text::{Line, Span, Text},
     widgets::{Block, Borders, Paragraph, Wrap},
@@ -29,6 +32,8 @@ pub struct HttpRequestMessage(pub HitmanRequest);
 pub struct HttpMessage {
     pub header: String,
     pub body: String,
+    /// File extension of the syntax used to highlight the body
+    pub syntax: &'static str,
 }
 
 pub struct HttpRequestInfo {
@@ -40,6 +45,10 @@ impl HttpRequestInfo {
     pub fn new(request: HttpRequestMessage, status: RequestStatus) -> Self {
         Self { request, status }
     }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.status, RequestStatus::Complete { .. })
+    }
 }
 
 pub enum RequestStatus {
@@ -58,7 +67,9 @@ pub enum Content {
     #[default]
     Empty,
     Preview(String),
-    Request(HttpRequestInfo),
+    Request(Box<HttpRequestInfo>),
+    Transcript(String),
+    Comparison(Box<Comparison>),
 }
 
 pub struct OutputView {
@@ -87,11 +98,21 @@ impl OutputView {
 
     pub fn show_request(&mut self, info: HttpRequestInfo) {
         if let RequestStatus::Complete { response, .. } = &info.status {
-            self.highlighter.update("json", &response.body);
+            self.highlighter.update(response.syntax, &response.body);
         }
 
         self.scroll = (0, 0);
-        self.content = Content::Request(info);
+        self.content = Content::Request(Box::new(info));
+    }
+
+    pub fn show_transcript(&mut self, text: String) {
+        self.scroll = (0, 0);
+        self.content = Content::Transcript(text);
+    }
+
+    pub fn show_comparison(&mut self, comparison: Comparison) {
+        self.scroll = (0, 0);
+        self.content = Content::Comparison(Box::new(comparison));
     }
 
     pub fn reset(&mut self) {
@@ -116,6 +137,8 @@ impl OutputView {
             Content::Empty => "",
             Content::Preview(_) => "Preview",
             Content::Request(_) => "Output",
+            Content::Transcript(_) => "Flow",
+            Content::Comparison(_) => "Compare",
         }
     }
 
@@ -131,7 +154,7 @@ impl OutputView {
         s
     }
 
-    fn make_lines(&self) -> Vec<Line> {
+    fn make_lines(&self) -> Vec<Line<'_>> {
         let mut lines: Vec<Line> = Vec::new();
 
         match &self.content {
@@ -182,13 +205,73 @@ impl OutputView {
                     .map(|line| Line::styled(format!("> {line}"), blue));
                 lines.extend(req_lines);
             }
+            Content::Transcript(text) => {
+                lines.extend(text.lines().map(Line::from));
+            }
+            // Rendered in two panes by render_comparison
+            Content::Comparison(_) => {}
         }
         lines
     }
+
+    fn render_comparison(
+        &self,
+        comparison: &Comparison,
+        frame: &mut Frame,
+        area: Rect,
+    ) {
+        let layout = Layout::new(
+            Direction::Horizontal,
+            [Constraint::Percentage(50), Constraint::Percentage(50)],
+        )
+        .split(area);
+
+        for ((lines, target), area) in comparison_lines(comparison)
+            .into_iter()
+            .zip(&comparison.targets)
+            .zip(layout.iter())
+        {
+            let block = Block::default()
+                .title(target.as_str())
+                .borders(Borders::ALL)
+                .border_set(ratatui::symbols::border::ROUNDED);
+            let para = Paragraph::new(Text::from(lines))
+                .scroll(self.scroll)
+                .block(block);
+            frame.render_widget(para, *area);
+        }
+    }
+}
+
+/// Aligned lines for the left and right panes, with differences marked.
+fn comparison_lines(comparison: &Comparison) -> [Vec<Line<'static>>; 2] {
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+
+    for row in comparison.rows() {
+        match row {
+            Row::Same(s) => {
+                left.push(Line::from(s.clone()));
+                right.push(Line::from(s));
+            }
+            Row::Changed { left: l, right: r } => {
+                let (red, green) = (Style::new().red(), Style::new().green());
+                left.push(Line::styled(l.unwrap_or_default(), red));
+                right.push(Line::styled(r.unwrap_or_default(), green));
+            }
+        }
+    }
+
+    [left, right]
 }
 
 impl Component for OutputView {
     fn render_ui(&mut self, frame: &mut Frame, area: Rect) {
+        if let Content::Comparison(comparison) = &self.content {
+            self.render_comparison(comparison, frame, area);
+            return;
+        }
+
         let title_bottom = if let Content::Request(info) = &self.content {
             if let RequestStatus::Complete { elapsed, .. } = &info.status {
                 format!("Elapsed: {elapsed:.2?}")
@@ -272,7 +355,7 @@ impl SyntaxHighlighter {
         }
     }
 
-    fn lines(&self) -> Option<Vec<Line>> {
+    fn lines(&self) -> Option<Vec<Line<'_>>> {
         self.cache.as_ref().map(|lines| {
             lines
                 .iter()
@@ -293,6 +376,7 @@ impl SyntaxHighlighter {
     fn update(&mut self, extension: &str, text: &str) {
         let Some(syntax) = self.syntax_set.find_syntax_by_extension(extension)
         else {
+            self.cache = None;
             return;
         };
 
@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Context as _, Result};
+use sxd_document::{
+    dom::{ChildOfElement, Document, Element},
+    parser,
+    writer::Writer,
+    Package,
+};
+use sxd_xpath::{nodeset::Node, Context, Factory, Value as XPathValue};
+use toml::{Table, Value};
+
+const DECLARATION: &str = r#"<?xml version="1.0"?>"#;
+
+/// Indent an XML document, two spaces per level.
+pub fn pretty_print(text: &str) -> Result<String> {
+    let package = parser::parse(text).context("Invalid XML")?;
+    let document = package.as_document();
+    for child in document.root().children() {
+        if let Some(element) = child.element() {
+            indent(&document, element, 0);
+        }
+    }
+
+    let mut out = Vec::new();
+    Writer::new()
+        .set_single_quotes(false)
+        .format_document(&document, &mut out)?;
+    let out = String::from_utf8(out)?;
+
+    // The writer always adds a declaration, only keep it if there was one
+    let body = out.strip_prefix(DECLARATION).unwrap_or(&out);
+    if text.trim_start().starts_with("<?xml") {
+        Ok(format!("{DECLARATION}\n{body}"))
+    } else {
+        Ok(body.to_string())
+    }
+}
+
+/// Put each child element on its own line, replacing the whitespace between
+/// them. Elements with text next to child elements are left as they are.
+fn indent<'d>(document: &Document<'d>, element: Element<'d>, depth: usize) {
+    let children = element.children();
+    let is_blank = |child: &ChildOfElement| {
+        child.text().is_some_and(|t| t.text().trim().is_empty())
+    };
+    let has_elements = children.iter().any(|c| c.element().is_some());
+    let has_text = children.iter().any(|c| c.text().is_some() && !is_blank(c));
+    if !has_elements || has_text {
+        return;
+    }
+
+    element.clear_children();
+    for child in children.into_iter().filter(|c| !is_blank(c)) {
+        let newline = format!("\n{}", "  ".repeat(depth + 1));
+        element.append_child(document.create_text(&newline));
+        if let Some(child) = child.element() {
+            indent(document, child, depth + 1);
+        }
+        element.append_child(child);
+    }
+    let newline = format!("\n{}", "  ".repeat(depth));
+    element.append_child(document.create_text(&newline));
+}
+
+/// A parsed XML response body, that values can be extracted from with
+/// XPath expressions.
+pub struct XmlDocument {
+    package: Package,
+}
+
+impl XmlDocument {
+    pub fn parse(text: &str) -> Result<Self> {
+        let package = parser::parse(text).context("Invalid XML")?;
+        Ok(Self { package })
+    }
+
+    /// The first value matching the expression.
+    pub fn select(&self, xpath: &str) -> Result<Option<Value>> {
+        let document = self.package.as_document();
+        let context = namespaces(&document);
+        let value = evaluate(&context, xpath, document.root().into())?;
+
+        Ok(to_toml(value))
+    }
+
+    /// Select a list of nodes, and a table of values from each of them,
+    /// like `{ _ = "//item", value = "@id", name = "name" }`.
+    pub fn select_items(
+        &self,
+        items: &str,
+        fields: &[(String, String)],
+    ) -> Result<Option<Vec<Table>>> {
+        let document = self.package.as_document();
+        let context = namespaces(&document);
+
+        let XPathValue::Nodeset(nodes) =
+            evaluate(&context, items, document.root().into())?
+        else {
+            return Ok(None);
+        };
+
+        let mut tables = Vec::new();
+        for node in nodes.document_order() {
+            let mut table = Table::new();
+            for (name, xpath) in fields {
+                if let Some(value) = to_toml(evaluate(&context, xpath, node)?) {
+                    table.insert(name.clone(), value);
+                }
+            }
+            tables.push(table);
+        }
+
+        Ok(Some(tables))
+    }
+}
+
+/// A context where all namespace prefixes declared in the document can be
+/// used in expressions, like `//soap:Body`.
+fn namespaces<'d>(document: &Document<'d>) -> Context<'d> {
+    let mut context = Context::new();
+
+    let mut elements: Vec<_> = document
+        .root()
+        .children()
+        .into_iter()
+        .filter_map(|child| child.element())
+        .collect();
+    while let Some(element) = elements.pop() {
+        for ns in element.namespaces_in_scope() {
+            context.set_namespace(ns.prefix(), ns.uri());
+        }
+        elements
+            .extend(element.children().into_iter().filter_map(|c| c.element()));
+    }
+
+    context
+}
+
+fn evaluate<'d>(
+    context: &Context<'d>,
+    xpath: &str,
+    node: Node<'d>,
+) -> Result<XPathValue<'d>> {
+    let compiled = Factory::new()
+        .build(xpath)
+        .map_err(|err| anyhow!("Invalid xpath '{xpath}': {err}"))?
+        .ok_or_else(|| anyhow!("Invalid xpath: {xpath}"))?;
+
+    compiled
+        .evaluate(context, node)
+        .map_err(|err| anyhow!("Invalid xpath '{xpath}': {err}"))
+}
+
+fn to_toml(value: XPathValue) -> Option<Value> {
+    match value {
+        XPathValue::Nodeset(nodes) => nodes
+            .document_order_first()
+            .map(|node| Value::String(node.string_value())),
+        XPathValue::String(s) => Some(Value::String(s)),
+        XPathValue::Boolean(b) => Some(Value::Boolean(b)),
+        #[allow(clippy::cast_possible_truncation)]
+        XPathValue::Number(n) if n.fract() == 0.0 => {
+            Some(Value::Integer(n as i64))
+        }
+        XPathValue::Number(n) if n.is_nan() => None,
+        XPathValue::Number(n) => Some(Value::Float(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOAP: &str = r#"<?xml version="1.0"?>
+<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope">
+  <soap:Body>
+    <items count="2">
+      <item id="1"><name>Apple</name></item>
+      <item id="2"><name>Pear</name></item>
+    </items>
+  </soap:Body>
+</soap:Envelope>"#;
+
+    #[test]
+    fn selects_values_with_namespaces() {
+        let doc = XmlDocument::parse(SOAP).unwrap();
+
+        assert_eq!(
+            doc.select("//soap:Body/items/item[2]/name").unwrap(),
+            Some(Value::from("Pear"))
+        );
+        assert_eq!(
+            doc.select("count(//item)").unwrap(),
+            Some(Value::Integer(2))
+        );
+        assert_eq!(doc.select("//missing").unwrap(), None);
+    }
+
+    #[test]
+    fn selects_items() {
+        let doc = XmlDocument::parse(SOAP).unwrap();
+        let fields = [
+            ("value".to_string(), "@id".to_string()),
+            ("name".to_string(), "name".to_string()),
+        ];
+
+        let items = doc.select_items("//item", &fields).unwrap().unwrap();
+
+        let expected: Table = toml::from_str(
+            r#"
+            [[items]]
+            value = "1"
+            name = "Apple"
+
+            [[items]]
+            value = "2"
+            name = "Pear"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Value::Array(items.into_iter().map(Value::Table).collect()),
+            expected["items"]
+        );
+    }
+
+    #[test]
+    fn indents_xml() {
+        assert_eq!(
+            pretty_print("<a><b x=\"1\">text</b><c/></a>").unwrap(),
+            "<a>\n  <b x=\"1\">text</b>\n  <c/>\n</a>"
+        );
+        assert_eq!(
+            pretty_print("<a>\n<b>x <i>y</i></b>  </a>").unwrap(),
+            "<a>\n  <b>x <i>y</i></b>\n</a>"
+        );
+        assert!(pretty_print(SOAP)
+            .unwrap()
+            .starts_with("<?xml version=\"1.0\"?>\n<soap:Envelope"));
+    }
+}